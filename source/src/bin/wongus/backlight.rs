@@ -0,0 +1,139 @@
+use {
+    futures_util::StreamExt,
+    inotify::{
+        Inotify,
+        WatchMask,
+    },
+    loga::{
+        ea,
+        DebugDisplay,
+        ErrContext,
+        Log,
+        ResultContext,
+    },
+    serde::Serialize,
+    std::path::{
+        Path,
+        PathBuf,
+    },
+    tokio::fs::read_dir,
+};
+
+const SYSFS_BACKLIGHT: &str = "/sys/class/backlight";
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct BacklightState {
+    pub name: String,
+    pub brightness: u32,
+    pub max_brightness: u32,
+    /// Brightness as a percent of max brightness (0-100).
+    pub percent: f64,
+}
+
+pub fn raw_to_percent(raw: u32, max: u32) -> f64 {
+    if max == 0 {
+        return 0.;
+    }
+    return raw as f64 / max as f64 * 100.;
+}
+
+pub fn percent_to_raw(percent: f64, max: u32) -> u32 {
+    return (percent.clamp(0., 100.) / 100. * max as f64).round() as u32;
+}
+
+fn device_dir(name: &str) -> Result<PathBuf, loga::Error> {
+    if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+        return Err(loga::err_with("Invalid backlight device name", ea!(name = name)));
+    }
+    return Ok(Path::new(SYSFS_BACKLIGHT).join(name));
+}
+
+async fn read_u32(path: &Path) -> Result<u32, loga::Error> {
+    let text =
+        tokio::fs::read_to_string(path)
+            .await
+            .context_with("Error reading backlight attribute", ea!(path = path.dbg_str()))?;
+    return Ok(
+        text
+            .trim()
+            .parse::<u32>()
+            .context_with("Backlight attribute isn't an integer", ea!(path = path.dbg_str(), text = text))?,
+    );
+}
+
+pub async fn get(name: &str) -> Result<BacklightState, loga::Error> {
+    let dir = device_dir(name)?;
+    let brightness = read_u32(&dir.join("actual_brightness")).await?;
+    let max_brightness = read_u32(&dir.join("max_brightness")).await?;
+    return Ok(BacklightState {
+        name: name.to_string(),
+        brightness: brightness,
+        max_brightness: max_brightness,
+        percent: raw_to_percent(brightness, max_brightness),
+    });
+}
+
+/// All readable backlight devices. Devices that can't be read (ex: still being
+/// registered) are logged and skipped.
+pub async fn list(log: &Log) -> Result<Vec<BacklightState>, loga::Error> {
+    let mut out = vec![];
+    let mut entries = match read_dir(SYSFS_BACKLIGHT).await {
+        Ok(e) => e,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(out);
+        },
+        Err(e) => {
+            return Err(e.context_with("Error listing backlight devices", ea!(path = SYSFS_BACKLIGHT)));
+        },
+    };
+    while let Some(entry) = entries.next_entry().await.context("Error reading backlight devices")? {
+        let Some(name) = entry.file_name().to_str().map(|x| x.to_string()) else {
+            continue;
+        };
+        match get(&name).await {
+            Ok(state) => out.push(state),
+            Err(e) => {
+                log.log_err(loga::WARN, e.context_with("Error reading backlight device, skipping", ea!(name = name)));
+            },
+        }
+    }
+    out.sort_by(|a, b| a.name.cmp(&b.name));
+    return Ok(out);
+}
+
+/// Set the raw brightness via logind, which allows the user owning the active
+/// session to change the brightness without root.
+pub async fn set(name: &str, raw: u32) -> Result<(), loga::Error> {
+    device_dir(name)?;
+    let conn = zbus::Connection::system().await.context("Error connecting to system D-Bus")?;
+    conn
+        .call_method(
+            Some("org.freedesktop.login1"),
+            "/org/freedesktop/login1/session/auto",
+            Some("org.freedesktop.login1.Session"),
+            "SetBrightness",
+            &("backlight", name, raw),
+        )
+        .await
+        .context_with("Error setting brightness via logind", ea!(name = name, brightness = raw.to_string()))?;
+    return Ok(());
+}
+
+/// Calls `cb` with the current state and then again each time the brightness
+/// changes. Only returns on error.
+pub async fn watch(name: &str, mut cb: impl FnMut(BacklightState)) -> Result<(), loga::Error> {
+    let path = device_dir(name)?.join("actual_brightness");
+    let inotify = Inotify::init().context("Error initializing inotify")?;
+    inotify
+        .watches()
+        .add(&path, WatchMask::MODIFY)
+        .context_with("Error watching backlight brightness", ea!(path = path.dbg_str()))?;
+    let mut events = inotify.into_event_stream([0u8; 1024]).context("Error creating inotify event stream")?;
+    cb(get(name).await?);
+    while let Some(e) = events.next().await {
+        e.context("Error reading inotify event")?;
+        cb(get(name).await?);
+    }
+    return Err(loga::err("Inotify event stream ended unexpectedly"));
+}
@@ -0,0 +1,205 @@
+use {
+    loga::{
+        ea,
+        DebugDisplay,
+        ResultContext,
+    },
+    std::{
+        collections::HashMap,
+        fs::File,
+        io::{
+            Read,
+            Write,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
+        sync::Arc,
+        time::SystemTime,
+    },
+};
+
+enum BundleFormat {
+    Zip,
+    Tar,
+}
+
+fn bundle_format(path: &Path) -> Result<BundleFormat, loga::Error> {
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+        Some("zip") => return Ok(BundleFormat::Zip),
+        Some("tar") => return Ok(BundleFormat::Tar),
+        _ => return Err(
+            loga::err_with("Unsupported bundle format, must have extension .zip or .tar", ea!(path = path.dbg_str())),
+        ),
+    }
+}
+
+/// Normalize an archive entry path to the form used for lookups: relative, no
+/// leading `./` or `/`.
+fn normalize(name: &str) -> String {
+    let mut name = name;
+    loop {
+        if let Some(n) = name.strip_prefix("./") {
+            name = n;
+        } else if let Some(n) = name.strip_prefix('/') {
+            name = n;
+        } else {
+            break;
+        }
+    }
+    return name.to_string();
+}
+
+/// Content (config, html, assets) loaded from a single archive file, held in memory.
+pub struct Bundle {
+    /// Modification time of the archive, used for all files.
+    pub mtime: Option<SystemTime>,
+    files: HashMap<String, Arc<Vec<u8>>>,
+}
+
+impl Bundle {
+    pub fn load(path: &Path) -> Result<Bundle, loga::Error> {
+        let format = bundle_format(path)?;
+        let file = File::open(path).context_with("Error opening bundle", ea!(path = path.dbg_str()))?;
+        let mtime = file.metadata().ok().and_then(|m| m.modified().ok());
+        let mut files = HashMap::new();
+        match format {
+            BundleFormat::Zip => {
+                let mut archive =
+                    zip::ZipArchive::new(file).context_with("Error reading zip bundle", ea!(path = path.dbg_str()))?;
+                for i in 0 .. archive.len() {
+                    let mut entry =
+                        archive.by_index(i).context_with("Error reading zip bundle entry", ea!(path = path.dbg_str()))?;
+                    if entry.is_dir() {
+                        continue;
+                    }
+                    let name = normalize(entry.name());
+                    let mut data = vec![];
+                    entry
+                        .read_to_end(&mut data)
+                        .context_with("Error extracting zip bundle entry", ea!(path = path.dbg_str(), entry = &name))?;
+                    files.insert(name, Arc::new(data));
+                }
+            },
+            BundleFormat::Tar => {
+                let mut archive = tar::Archive::new(file);
+                for entry in archive.entries().context_with("Error reading tar bundle", ea!(path = path.dbg_str()))? {
+                    let mut entry = entry.context_with("Error reading tar bundle entry", ea!(path = path.dbg_str()))?;
+                    if !entry.header().entry_type().is_file() {
+                        continue;
+                    }
+                    let name =
+                        normalize(
+                            &entry
+                                .path()
+                                .context_with("Error reading tar bundle entry path", ea!(path = path.dbg_str()))?
+                                .to_string_lossy(),
+                        );
+                    let mut data = vec![];
+                    entry
+                        .read_to_end(&mut data)
+                        .context_with("Error extracting tar bundle entry", ea!(path = path.dbg_str(), entry = &name))?;
+                    files.insert(name, Arc::new(data));
+                }
+            },
+        }
+        return Ok(Bundle {
+            mtime: mtime,
+            files: files,
+        });
+    }
+
+    /// Get a file by path (relative, or absolute from the bundle root).
+    pub fn get(&self, path: &str) -> Option<Arc<Vec<u8>>> {
+        return self.files.get(&normalize(path)).cloned();
+    }
+}
+
+/// Collect files under `dir`, skipping `exclude` (the bundle being written).
+/// Symlinks to files are followed, symlinks to directories are skipped to avoid
+/// loops.
+fn walk(root: &Path, dir: &Path, exclude: &Path, out: &mut Vec<(PathBuf, String)>) -> Result<(), loga::Error> {
+    for entry in std::fs::read_dir(dir).context_with("Error listing directory", ea!(path = dir.dbg_str()))? {
+        let entry = entry.context_with("Error reading directory entry", ea!(path = dir.dbg_str()))?;
+        let path = entry.path();
+        if path == exclude {
+            continue;
+        }
+        let meta =
+            std::fs::symlink_metadata(&path).context_with("Error reading file metadata", ea!(path = path.dbg_str()))?;
+        if meta.is_dir() {
+            walk(root, &path, exclude, out)?;
+        } else if meta.is_symlink() &&
+            std::fs::metadata(&path)
+                .context_with("Error reading symlink target metadata", ea!(path = path.dbg_str()))?
+                .is_dir() {
+            continue;
+        } else {
+            let name =
+                path
+                    .strip_prefix(root)
+                    .unwrap()
+                    .to_str()
+                    .context_with("Bundle file path isn't valid utf-8", ea!(path = path.dbg_str()))?
+                    .to_string();
+            out.push((path, name));
+        }
+    }
+    return Ok(());
+}
+
+/// Create a bundle (zip or tar, depending on the extension of `out`) from a
+/// directory.
+pub fn pack(dir: &Path, out: &Path) -> Result<(), loga::Error> {
+    let format = bundle_format(out)?;
+    for required in ["config.json", "index.html"] {
+        if !dir.join(required).exists() {
+            return Err(
+                loga::err_with("Directory is missing a required file", ea!(path = dir.dbg_str(), file = required)),
+            );
+        }
+    }
+    // Compare canonical paths so the output is excluded if it's inside `dir`
+    let dir = dir.canonicalize().context_with("Error making directory path absolute", ea!(path = dir.dbg_str()))?;
+    let exclude =
+        out
+            .parent()
+            .map(|p| if p.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                p
+            })
+            .unwrap_or(Path::new("."))
+            .canonicalize()
+            .context_with("Error making bundle path absolute", ea!(path = out.dbg_str()))?
+            .join(out.file_name().context_with("Bundle path has no file name", ea!(path = out.dbg_str()))?);
+    let mut files = vec![];
+    walk(&dir, &dir, &exclude, &mut files)?;
+    files.sort_by(|a, b| a.1.cmp(&b.1));
+    let out_file = File::create(out).context_with("Error creating bundle file", ea!(path = out.dbg_str()))?;
+    match format {
+        BundleFormat::Zip => {
+            let mut zip = zip::ZipWriter::new(out_file);
+            for (path, name) in files {
+                zip
+                    .start_file(&name, zip::write::SimpleFileOptions::default())
+                    .context_with("Error adding file to zip bundle", ea!(path = path.dbg_str()))?;
+                zip
+                    .write_all(&std::fs::read(&path).context_with("Error reading file", ea!(path = path.dbg_str()))?)
+                    .context_with("Error writing file to zip bundle", ea!(path = path.dbg_str()))?;
+            }
+            zip.finish().context_with("Error finishing zip bundle", ea!(path = out.dbg_str()))?;
+        },
+        BundleFormat::Tar => {
+            let mut tar = tar::Builder::new(out_file);
+            for (path, name) in files {
+                tar
+                    .append_path_with_name(&path, &name)
+                    .context_with("Error adding file to tar bundle", ea!(path = path.dbg_str()))?;
+            }
+            tar.finish().context_with("Error finishing tar bundle", ea!(path = out.dbg_str()))?;
+        },
+    }
+    return Ok(());
+}
@@ -0,0 +1,158 @@
+use {
+    crate::{
+        popup::window_of,
+        UserEvent,
+    },
+    base64::{
+        prelude::BASE64_STANDARD,
+        Engine,
+    },
+    gtk::{
+        gdk::{
+            Atom,
+            SELECTION_CLIPBOARD,
+        },
+        prelude::ObjectExt,
+        TargetEntry,
+        TargetFlags,
+    },
+    loga::ResultContext,
+    serde::Deserialize,
+    serde_json::json,
+    std::{
+        cell::RefCell,
+        rc::Rc,
+    },
+    tao::event_loop::EventLoopProxy,
+    tokio::sync::oneshot,
+};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct IPCReqClipboardWrite {
+    mime: String,
+    /// Base64-encoded data.
+    data: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IPCReqClipboard {
+    ReadText,
+    WriteText(String),
+    /// Read the clipboard contents in the specified mime type, returning base64.
+    Read(String),
+    Write(IPCReqClipboardWrite),
+    /// Call the stream cb with this id with the new text whenever the clipboard
+    /// changes.
+    Watch(usize),
+}
+
+fn respond(resp: oneshot::Sender<Result<serde_json::Value, loga::Error>>, r: Result<serde_json::Value, loga::Error>) {
+    match resp.send(r) {
+        Ok(_) => (),
+        Err(_) => (),
+    };
+}
+
+/// Main thread clipboard state.
+#[derive(Clone)]
+pub struct Clipboard {
+    clipboard: gtk::Clipboard,
+    watchers: Rc<RefCell<Vec<usize>>>,
+}
+
+impl Clipboard {
+    pub fn new(event_loop: EventLoopProxy<UserEvent>) -> Self {
+        let clipboard = gtk::Clipboard::get(&SELECTION_CLIPBOARD);
+        let watchers = Rc::new(RefCell::new(Vec::<usize>::new()));
+        clipboard.connect_local("owner-change", false, {
+            let watchers = watchers.clone();
+            move |args| {
+                if watchers.borrow().is_empty() {
+                    return None;
+                }
+                let clipboard = args[0].get::<gtk::Clipboard>().unwrap();
+                clipboard.request_text({
+                    let watchers = watchers.clone();
+                    let event_loop = event_loop.clone();
+                    move |_, text| {
+                        let text = serde_json::to_string(&text).unwrap();
+                        for id in watchers.borrow().iter() {
+                            match event_loop.send_event(
+                                UserEvent::Script(
+                                    window_of(*id),
+                                    format!("(window._wongus.stream_cbs.get({}))({});", id, text),
+                                ),
+                            ) {
+                                Ok(_) => (),
+                                Err(_) => (),
+                            };
+                        }
+                    }
+                });
+                return None;
+            }
+        });
+        return Clipboard {
+            clipboard: clipboard,
+            watchers: watchers,
+        };
+    }
+
+    /// Drop watchers, for when the page navigates and the callbacks are gone.
+    pub fn clear_watchers(&self) {
+        self.watchers.borrow_mut().clear();
+    }
+
+    /// Drop watchers from a window, for when a popup navigates or closes.
+    pub fn clear_window(&self, window: usize) {
+        self.watchers.borrow_mut().retain(|id| window_of(*id) != window);
+    }
+
+    pub fn handle(&self, req: IPCReqClipboard, resp: oneshot::Sender<Result<serde_json::Value, loga::Error>>) {
+        match req {
+            IPCReqClipboard::ReadText => {
+                self.clipboard.request_text(move |_, text| {
+                    respond(resp, Ok(json!(text)));
+                });
+            },
+            IPCReqClipboard::WriteText(text) => {
+                self.clipboard.set_text(&text);
+                respond(resp, Ok(json!({ })));
+            },
+            IPCReqClipboard::Read(mime) => {
+                self.clipboard.request_contents(&Atom::intern(&mime), move |_, data| {
+                    if data.length() < 0 {
+                        respond(resp, Ok(json!(null)));
+                    } else {
+                        respond(resp, Ok(json!(BASE64_STANDARD.encode(data.data()))));
+                    }
+                });
+            },
+            IPCReqClipboard::Write(req) => {
+                let data = match BASE64_STANDARD.decode(&req.data).context("Clipboard data isn't valid base64") {
+                    Ok(d) => d,
+                    Err(e) => {
+                        respond(resp, Err(e));
+                        return;
+                    },
+                };
+                let atom = Atom::intern(&req.mime);
+                if !self
+                    .clipboard
+                    .set_with_data(&[TargetEntry::new(&req.mime, TargetFlags::empty(), 0)], move |_, selection, _| {
+                        selection.set(&atom, 8, &data);
+                    }) {
+                    respond(resp, Err(loga::err("Failed to take clipboard ownership")));
+                    return;
+                }
+                respond(resp, Ok(json!({ })));
+            },
+            IPCReqClipboard::Watch(id) => {
+                self.watchers.borrow_mut().push(id);
+                respond(resp, Ok(json!({ })));
+            },
+        }
+    }
+}
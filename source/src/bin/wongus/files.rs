@@ -0,0 +1,299 @@
+use {
+    base64::{
+        prelude::BASE64_STANDARD,
+        Engine,
+    },
+    loga::{
+        ea,
+        DebugDisplay,
+        ErrContext,
+        ResultContext,
+    },
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    std::{
+        fs::Metadata,
+        os::unix::{
+            ffi::OsStrExt,
+            fs::PermissionsExt,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
+        sync::atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        time::UNIX_EPOCH,
+    },
+    tokio::io::{
+        AsyncReadExt,
+        AsyncSeekExt,
+        AsyncWriteExt,
+        SeekFrom,
+    },
+};
+
+/// File contents from js. Exactly one of the fields must be set.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct IPCFileData {
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub base64: Option<String>,
+}
+
+impl IPCFileData {
+    pub fn into_bytes(self) -> Result<Vec<u8>, loga::Error> {
+        match (self.text, self.base64) {
+            (Some(text), None) => return Ok(text.into_bytes()),
+            (None, Some(b64)) => return Ok(BASE64_STANDARD.decode(&b64).context("Data isn't valid base64")?),
+            _ => return Err(loga::err("Exactly one of `text` or `base64` must be set")),
+        }
+    }
+}
+
+/// Replace the file contents by writing to a temp file in the same directory and
+/// renaming it over the original, so readers never see a partial file. The
+/// original file's permissions are preserved.
+pub async fn write_atomic(path: &Path, data: &[u8]) -> Result<(), loga::Error> {
+    // Unique per write so concurrent writes to the same path don't share a temp file
+    static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+    let file_name = path.file_name().context_with("Write path has no file name", ea!(path = path.dbg_str()))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".wongus-{}-{}.tmp", std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let temp_path = path.with_file_name(temp_name);
+    let res = async {
+        tokio::fs::write(&temp_path, data)
+            .await
+            .context_with("Error writing temp file", ea!(path = temp_path.dbg_str()))?;
+        if let Ok(meta) = tokio::fs::metadata(path).await {
+            tokio::fs::set_permissions(&temp_path, meta.permissions())
+                .await
+                .context_with("Error copying permissions to temp file", ea!(path = temp_path.dbg_str()))?;
+        }
+        tokio::fs::rename(&temp_path, path)
+            .await
+            .context_with("Error replacing file with temp file", ea!(path = path.dbg_str()))?;
+        return Ok(());
+    }.await;
+    if res.is_err() {
+        _ = tokio::fs::remove_file(&temp_path).await;
+    }
+    return res;
+}
+
+pub async fn append(path: &Path, data: &[u8]) -> Result<(), loga::Error> {
+    let mut f =
+        tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .context_with("Error opening file for appending", ea!(path = path.dbg_str()))?;
+    f.write_all(data).await.context_with("Error appending to file", ea!(path = path.dbg_str()))?;
+    f.flush().await.context_with("Error flushing appended data", ea!(path = path.dbg_str()))?;
+    return Ok(());
+}
+
+/// Remove a file or (if `recursive`) a directory and its contents. Empty
+/// directories can be removed without `recursive`.
+pub async fn remove(path: &Path, recursive: bool) -> Result<(), loga::Error> {
+    let meta =
+        tokio::fs::symlink_metadata(path)
+            .await
+            .context_with("Error reading metadata of path to remove", ea!(path = path.dbg_str()))?;
+    if !meta.is_dir() {
+        tokio::fs::remove_file(path).await.context_with("Error removing file", ea!(path = path.dbg_str()))?;
+    } else if recursive {
+        tokio::fs::remove_dir_all(path)
+            .await
+            .context_with("Error removing directory recursively", ea!(path = path.dbg_str()))?;
+    } else {
+        tokio::fs::remove_dir(path).await.context_with("Error removing directory", ea!(path = path.dbg_str()))?;
+    }
+    return Ok(());
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+    Other,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct FileStat {
+    /// Lossy if the path isn't valid utf-8.
+    pub path: String,
+    /// The exact path bytes, only set if the path isn't valid utf-8.
+    pub path_bytes: Option<Vec<u8>>,
+    pub file_type: FileType,
+    pub size: u64,
+    /// Modification time, milliseconds since the epoch.
+    pub mtime: Option<f64>,
+    /// Unix permission bits (ex: `0o644`).
+    pub mode: u32,
+    /// Only set for symlinks.
+    pub symlink_target: Option<String>,
+}
+
+/// Returns `None` if the file was removed while reading its details.
+async fn file_stat(path: &Path, meta: Metadata) -> Result<Option<FileStat>, loga::Error> {
+    let file_type = meta.file_type();
+    let symlink_target = if file_type.is_symlink() {
+        match tokio::fs::read_link(path).await {
+            Ok(t) => Some(t.to_string_lossy().to_string()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.context_with("Error reading symlink target", ea!(path = path.dbg_str()))),
+        }
+    } else {
+        None
+    };
+    return Ok(Some(FileStat {
+        path: path.to_string_lossy().to_string(),
+        path_bytes: if path.to_str().is_none() {
+            Some(path.as_os_str().as_bytes().to_vec())
+        } else {
+            None
+        },
+        file_type: if file_type.is_symlink() {
+            FileType::Symlink
+        } else if file_type.is_dir() {
+            FileType::Dir
+        } else if file_type.is_file() {
+            FileType::File
+        } else {
+            FileType::Other
+        },
+        size: meta.len(),
+        mtime: meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs_f64() * 1000.),
+        mode: meta.permissions().mode() & 0o7777,
+        symlink_target: symlink_target,
+    }));
+}
+
+/// Stat a path without following symlinks, returning `None` if it doesn't exist.
+pub async fn stat(path: &Path) -> Result<Option<FileStat>, loga::Error> {
+    let meta = match tokio::fs::symlink_metadata(path).await {
+        Ok(m) => m,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.context_with("Error reading file metadata", ea!(path = path.dbg_str()))),
+    };
+    return file_stat(path, meta).await;
+}
+
+/// List a directory, recursing into subdirectories (not symlinks) up to `depth`
+/// levels (1 = only direct children). If `pattern` is set, only entries whose
+/// file name matches are returned, but all subdirectories are still traversed.
+/// Entries removed while listing are skipped.
+pub async fn list_dir_stat(
+    path: &Path,
+    depth: usize,
+    pattern: Option<&glob::Pattern>,
+) -> Result<Vec<FileStat>, loga::Error> {
+    let mut out = vec![];
+    let mut stack = vec![(PathBuf::from(path), 1usize)];
+    while let Some((dir, dir_depth)) = stack.pop() {
+        let mut entries =
+            tokio::fs::read_dir(&dir).await.context_with("Error listing directory", ea!(path = dir.dbg_str()))?;
+        while let Some(entry) = entries.next_entry().await.context("Error reading directory entries")? {
+            let entry_path = entry.path();
+            let meta = match entry.metadata().await {
+                Ok(m) => m,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(
+                        e.context_with("Error reading directory entry metadata", ea!(path = entry_path.dbg_str())),
+                    );
+                },
+            };
+            if meta.is_dir() && dir_depth < depth {
+                stack.push((entry_path.clone(), dir_depth + 1));
+            }
+            if let Some(pattern) = pattern {
+                if !pattern.matches(&entry.file_name().to_string_lossy()) {
+                    continue;
+                }
+            }
+            if let Some(stat) = file_stat(&entry_path, meta).await? {
+                out.push(stat);
+            }
+        }
+    }
+    out.sort_by(|a, b| a.path.cmp(&b.path));
+    return Ok(out);
+}
+
+/// Read `length` bytes (or to the end of the file if `None`) starting at `offset`.
+/// The result may be shorter than `length` if the end of the file is reached.
+pub async fn read_range(path: &Path, offset: u64, length: Option<u64>) -> Result<Vec<u8>, loga::Error> {
+    let mut f =
+        tokio::fs::File::open(path).await.context_with("Error opening file for reading", ea!(path = path.dbg_str()))?;
+    f.seek(SeekFrom::Start(offset)).await.context_with("Error seeking in file", ea!(path = path.dbg_str()))?;
+    let mut out = vec![];
+    match length {
+        Some(length) => {
+            f.take(length).read_to_end(&mut out).await
+        },
+        None => {
+            f.read_to_end(&mut out).await
+        },
+    }.context_with("Error reading file", ea!(path = path.dbg_str()))?;
+    return Ok(out);
+}
+
+/// Read the last `lines` lines of a text file, without reading the whole file.
+pub async fn read_tail(path: &Path, lines: usize) -> Result<String, loga::Error> {
+    const CHUNK: u64 = 64 * 1024;
+    if lines == 0 {
+        return Ok(String::new());
+    }
+    let mut f =
+        tokio::fs::File::open(path).await.context_with("Error opening file for reading", ea!(path = path.dbg_str()))?;
+    let mut end = f.seek(SeekFrom::End(0)).await.context_with("Error seeking in file", ea!(path = path.dbg_str()))?;
+    let mut out = vec![];
+    loop {
+        // Find the newline before the first wanted line, ignoring any newline at the
+        // very end of the file
+        let body = out.strip_suffix(b"\n").unwrap_or(&out);
+        let mut found = None;
+        let mut count = 0;
+        for (i, c) in body.iter().enumerate().rev() {
+            if *c == b'\n' {
+                count += 1;
+                if count == lines {
+                    found = Some(i);
+                    break;
+                }
+            }
+        }
+        if let Some(i) = found {
+            out.drain(..= i);
+            break;
+        }
+        if end == 0 {
+            break;
+        }
+        let start = end.saturating_sub(CHUNK);
+        f.seek(SeekFrom::Start(start)).await.context_with("Error seeking in file", ea!(path = path.dbg_str()))?;
+        let mut chunk = vec![0u8; (end - start) as usize];
+        f.read_exact(&mut chunk).await.context_with("Error reading file", ea!(path = path.dbg_str()))?;
+        chunk.extend(out);
+        out = chunk;
+        end = start;
+    }
+    return Ok(String::from_utf8(out).context_with("File isn't valid utf-8", ea!(path = path.dbg_str()))?);
+}
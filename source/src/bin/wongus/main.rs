@@ -0,0 +1,2233 @@
+use {
+    aargvark::{
+        help::{
+            HelpPattern,
+            HelpPatternElement,
+            HelpState,
+        },
+        traits_impls::{
+            AargvarkFromStr,
+            AargvarkTrait,
+        },
+        vark_explicit,
+        Aargvark,
+        VarkRet,
+    },
+    flowcontrol::{
+        shed,
+    },
+    gtk::{
+        cairo::{
+            RectangleInt,
+            Region,
+        },
+        gdk::Screen,
+        glib::{
+            CastNone,
+        },
+        prelude::{
+            ContainerExt,
+            GtkWindowExt,
+            MonitorExt,
+            WidgetExt,
+        },
+        ApplicationWindow,
+    },
+    gtk_layer_shell::LayerShell,
+    http::{
+        Request,
+        Response,
+    },
+    http_body_util::BodyExt,
+    htwrap::htserve::responses::{
+        response_200_json,
+        response_400,
+        response_503,
+        response_503_text,
+        Body,
+    },
+    hyper::body::Incoming,
+    loga::{
+        ea,
+        fatal,
+        DebugDisplay,
+        ErrContext,
+        Log,
+        ResultContext,
+    },
+    serde::Deserialize,
+    serde_json::json,
+    std::{
+        cell::{
+            Cell,
+            RefCell,
+        },
+        collections::HashMap,
+        convert::Infallible,
+        env,
+        fs::remove_file,
+        ops::Deref,
+        path::PathBuf,
+        rc::Rc,
+        sync::{
+            atomic::{
+                AtomicUsize,
+                Ordering,
+            },
+            Arc,
+            Mutex,
+        },
+        thread::spawn,
+        time::Duration,
+    },
+    tao::{
+        event::{
+            Event,
+            WindowEvent,
+        },
+        event_loop::{
+            self,
+            ControlFlow,
+            EventLoopProxy,
+        },
+        platform::{
+            run_return::EventLoopExtRunReturn,
+            unix::{
+                EventLoopWindowTargetExtUnix,
+                WindowExtUnix,
+            },
+        },
+    },
+    tokio::{
+        fs::read_dir,
+        net::UnixSocket,
+        process::Command,
+        select,
+        signal::unix::{
+            signal,
+            SignalKind,
+        },
+        sync::{
+            mpsc::{
+                unbounded_channel,
+                UnboundedSender,
+            },
+            oneshot,
+        },
+        time::sleep,
+    },
+    base64::{
+        prelude::BASE64_STANDARD,
+        Engine,
+    },
+    clipboard::IPCReqClipboard,
+    files::IPCFileData,
+    wongus::{
+        Config,
+        KeyboardMode,
+        MonitorAxis,
+        MonitorSelector,
+        OnMissingMonitor,
+    },
+    wry::{
+        PageLoadEvent,
+        WebViewBuilder,
+        WebViewBuilderExtUnix,
+    },
+};
+
+mod backlight;
+mod bundle;
+mod clipboard;
+mod files;
+mod monitors;
+mod network;
+mod outputs;
+mod proxy;
+mod popup;
+mod pty;
+mod schedule;
+mod serve;
+mod services;
+mod shared_stream;
+mod stream;
+mod storage;
+mod system_events;
+
+struct ArgKv {
+    k: String,
+    v: String,
+}
+
+impl AargvarkFromStr for ArgKv {
+    fn from_str(s: &str) -> Result<Self, String> {
+        let Some((k, v)) = s.split_once("=") else {
+            return Err(format!("All arguments must be in the form KEY=VALUE here, but got [{}]", s));
+        };
+        return Ok(ArgKv {
+            k: k.to_string(),
+            v: v.to_string(),
+        });
+    }
+
+    fn build_help_pattern(_state: &mut HelpState) -> HelpPattern {
+        return HelpPattern(vec![HelpPatternElement::Type("KEY=VALUE".to_string())]);
+    }
+}
+
+#[derive(Aargvark)]
+struct Args {
+    /// Directory containing config.json, index.html and any other assets, or a `.zip`
+    /// or `.tar` bundle of them (see `wongus pack`).
+    content_root: PathBuf,
+    /// URL of a server to serve content from instead of `content_root`. `content_root`
+    /// will still be used for the config json, but the remaining files will be ignored.
+    server: Option<String>,
+    /// Serve `server` through wongus rather than loading it directly. A placeholder
+    /// page is shown while the server is unreachable, which reloads the page once the
+    /// server is back. Only `http` servers are supported.
+    proxy: Option<()>,
+    debug: Option<()>,
+    /// Additional arguments to be passed to the script.
+    args: Vec<ArgKv>,
+}
+
+/// `wongus pack`: pack a content directory into a bundle instead of showing the
+/// window.
+#[derive(Aargvark)]
+struct PackArgs {
+    /// Directory containing config.json, index.html and any other assets.
+    dir: PathBuf,
+    /// Path of the bundle to create, ending with `.zip` or `.tar`.
+    out: PathBuf,
+}
+
+/// Like `aargvark::vark` but with the arguments passed in.
+fn vark_args<T: AargvarkTrait>(command: Option<String>, args: Vec<String>) -> T {
+    match vark_explicit(command, args) {
+        Ok(VarkRet::Ok(v)) => return v,
+        Ok(VarkRet::Help(h)) => {
+            println!("{}", h.render());
+            std::process::exit(0);
+        },
+        Err(e) => {
+            eprintln!("{:?}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct IPCReqCommand {
+    command: Vec<String>,
+    /// By default uses the working directory of `wongus`.
+    #[serde(default)]
+    working_dir: Option<String>,
+    /// Add to environment inherited from `wongus` process.
+    #[serde(default)]
+    environment: HashMap<String, String>,
+    /// Timeout command if it takes too long; defaults to 10s.
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct IPCReqDetachedCommand {
+    command: Vec<String>,
+    /// By default uses the working directory of `wongus`.
+    #[serde(default)]
+    working_dir: Option<String>,
+    /// Add to environment inherited from `wongus` process.
+    #[serde(default)]
+    environment: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct IPCReqStreamCommand {
+    id: usize,
+    command: Vec<String>,
+    /// By default uses the working directory of `wongus`.
+    #[serde(default)]
+    working_dir: Option<String>,
+    /// Add to environment inherited from `wongus` process.
+    #[serde(default)]
+    environment: HashMap<String, String>,
+    /// Share one process between all shared streams with the same command, working
+    /// dir, and environment, including across reloads.
+    #[serde(default)]
+    shared: bool,
+    /// For shared streams, send up to this many of the most recent lines immediately.
+    #[serde(default)]
+    replay: Option<usize>,
+    #[serde(default)]
+    mode: stream::StreamMode,
+    /// Stream callback id for output that couldn't be parsed. If not set, errors are
+    /// logged to the console.
+    #[serde(default)]
+    error_id: Option<usize>,
+    /// Deliver values received within this many milliseconds of each other
+    /// together.
+    #[serde(default)]
+    batch_ms: Option<u64>,
+    /// Max values waiting to be handled by the page before `overflow` applies.
+    /// Defaults to 1000.
+    #[serde(default)]
+    queue_limit: Option<usize>,
+    #[serde(default)]
+    overflow: stream::OverflowPolicy,
+    /// Run the command in a pseudo-terminal of this size. Output is sent as raw
+    /// chunks, and input can be sent with `PtyWrite`.
+    #[serde(default)]
+    pty: Option<pty::IPCPtySize>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct IPCReqListDirStat {
+    path: PathBuf,
+    /// How many levels of directories to list, 1 (the default) for only the
+    /// directory's direct children.
+    #[serde(default)]
+    depth: Option<usize>,
+    /// Only return entries whose file name matches this glob pattern.
+    #[serde(default)]
+    glob: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct IPCReqReadBytes {
+    path: PathBuf,
+    /// Byte offset to start reading from, defaults to 0.
+    #[serde(default)]
+    offset: Option<u64>,
+    /// Max bytes to read, defaults to reading to the end of the file.
+    #[serde(default)]
+    length: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct IPCReqReadTail {
+    path: PathBuf,
+    lines: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct IPCReqWrite {
+    path: PathBuf,
+    #[serde(flatten)]
+    data: IPCFileData,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct IPCReqRemove {
+    path: PathBuf,
+    /// Required to remove non-empty directories.
+    #[serde(default)]
+    recursive: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct IPCReqRename {
+    from: PathBuf,
+    to: PathBuf,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct IPCReqBacklightSet {
+    /// Device name, a directory in `/sys/class/backlight`.
+    name: String,
+    /// Brightness as a percent of max brightness (0-100). Mutually exclusive with
+    /// `raw`.
+    #[serde(default)]
+    percent: Option<f64>,
+    /// Brightness in device units (0-`max_brightness`). Mutually exclusive with
+    /// `percent`.
+    #[serde(default)]
+    raw: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct IPCReqBacklightWatch {
+    id: usize,
+    /// Device name, a directory in `/sys/class/backlight`.
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct IPCReqNetworkWatch {
+    id: usize,
+    /// In addition to link and address changes, also send the state at this interval
+    /// (for traffic counters and wifi signal strength).
+    #[serde(default)]
+    refresh_secs: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct IPCReqStorageSet {
+    key: String,
+    value: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct IPCReqPtyWrite {
+    id: usize,
+    #[serde(flatten)]
+    data: IPCFileData,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct IPCReqPtyResize {
+    id: usize,
+    #[serde(flatten)]
+    size: pty::IPCPtySize,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct IPCReqServicesSubscribe {
+    id: usize,
+    name: String,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum WindowVisibility {
+    Hide,
+    Show,
+    Toggle,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum IPCReqBody {
+    Log(String),
+    ListDir(PathBuf),
+    ListDirStat(IPCReqListDirStat),
+    FileExists(PathBuf),
+    Stat(PathBuf),
+    Read(PathBuf),
+    /// Read binary data, returns base64.
+    ReadBytes(IPCReqReadBytes),
+    /// Read the last lines of a text file.
+    ReadTail(IPCReqReadTail),
+    /// Atomically replace the file contents.
+    Write(IPCReqWrite),
+    Append(IPCReqWrite),
+    /// Create a directory and any missing parents.
+    CreateDir(PathBuf),
+    Remove(IPCReqRemove),
+    Rename(IPCReqRename),
+    BacklightList,
+    BacklightGet(String),
+    BacklightSet(IPCReqBacklightSet),
+    BacklightWatch(IPCReqBacklightWatch),
+    NetworkGet,
+    NetworkWatch(IPCReqNetworkWatch),
+    Clipboard(IPCReqClipboard),
+    StorageGet(String),
+    StorageSet(IPCReqStorageSet),
+    StorageDelete(String),
+    StorageList,
+    ServicesSubscribe(IPCReqServicesSubscribe),
+    RunCommand(IPCReqCommand),
+    RunDetachedCommand(IPCReqDetachedCommand),
+    StreamCommand(IPCReqStreamCommand),
+    PtyWrite(IPCReqPtyWrite),
+    PtyResize(IPCReqPtyResize),
+    Schedule(schedule::IPCReqSchedule),
+    /// Hide or show the main window.
+    WindowVisibility(WindowVisibility),
+    /// Only accept clicks in these areas of the main window, or anywhere if null.
+    WindowSetInputRegion(Option<Vec<popup::IPCRect>>),
+    /// List all monitors.
+    Monitors,
+    /// The monitor the main window is on.
+    CurrentMonitor,
+    /// Take keyboard focus exclusively, like `keyboard_mode` `exclusive`.
+    WindowGrabKeyboard,
+    /// Return to the configured keyboard mode (or `on_demand` if it's `exclusive`).
+    WindowReleaseKeyboard,
+    /// The size of the page content changed, for `auto` window sizes. Sent by
+    /// `setup.js`.
+    WindowContentSize(popup::IPCSize),
+    PopupOpen(popup::IPCReqPopupOpen),
+    /// Close the popup with this window id.
+    PopupClose(usize),
+    /// Send a message between a popup and the main page.
+    PopupPost(popup::IPCReqPopupPost),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct WindowIpc {
+    id: usize,
+    body: IPCReqBody,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum ExternalIpcResp {
+    Ok(serde_json::Value),
+    Err(String),
+}
+
+enum UserEvent {
+    /// Script to run in the page with this window id.
+    Script(usize, String),
+    /// Values are waiting in `StreamQueues`.
+    FlushStreams,
+    ExternalScript(String, oneshot::Sender<ExternalIpcResp>),
+    Clipboard(IPCReqClipboard, oneshot::Sender<Result<serde_json::Value, loga::Error>>),
+    /// The login session was locked (true) or unlocked (false).
+    SessionLocked(bool),
+    WindowVisibility(WindowVisibility),
+    WindowSetInputRegion(Option<Vec<popup::IPCRect>>),
+    /// Take (true) or release (false) keyboard focus.
+    WindowGrabKeyboard(bool),
+    WindowContentSize(popup::IPCSize),
+    /// None of the configured monitors are attached (true) or one is again (false).
+    MonitorMissing(bool),
+    /// Get all monitors, or the current monitor if true.
+    Monitors(bool, oneshot::Sender<serde_json::Value>),
+    PopupOpen(popup::IPCReqPopupOpen, oneshot::Sender<Result<serde_json::Value, loga::Error>>),
+    PopupClose(usize),
+    /// Something other than a popup was clicked, close all popups except this one
+    /// (if set).
+    PopupCloseOthers(Option<usize>),
+    /// The main page navigated.
+    PopupCloseAll,
+    ErrExit(loga::Error),
+}
+
+fn layer_keyboard_mode(mode: KeyboardMode) -> gtk_layer_shell::KeyboardMode {
+    match mode {
+        KeyboardMode::None => return gtk_layer_shell::KeyboardMode::None,
+        KeyboardMode::OnDemand => return gtk_layer_shell::KeyboardMode::OnDemand,
+        KeyboardMode::Exclusive => return gtk_layer_shell::KeyboardMode::Exclusive,
+    }
+}
+
+/// Region of the window accepting input, from rects in css pixels.
+fn input_region(rects: Vec<popup::IPCRect>) -> Region {
+    let region = Region::create();
+    for r in rects {
+        let x = r.x.floor() as i32;
+        let y = r.y.floor() as i32;
+        _ = region.union_rectangle(
+            &RectangleInt::new(x, y, (r.x + r.width).ceil() as i32 - x, (r.y + r.height).ceil() as i32 - y),
+        );
+    }
+    return region;
+}
+
+/// What's needed to set up a webview with wongus's apis and file access, shared
+/// by the main page and popups.
+struct WebviewSetup {
+    rt: tokio::runtime::Handle,
+    log: Log,
+    ipc_req_tx: UnboundedSender<Vec<u8>>,
+    /// Sets `wongus.env` and `wongus.args`.
+    init_script: String,
+    filex_source: Arc<serve::ServeSource>,
+    file_source: Arc<serve::ServeSource>,
+    server_proxy: Option<Arc<proxy::Proxy>>,
+    /// Origins popups may load urls from, see `popup::origin_of`.
+    popup_origins: Vec<String>,
+}
+
+impl WebviewSetup {
+    fn builder<'a>(&self, window_id: usize) -> WebViewBuilder<'a> {
+        let mut webview = WebViewBuilder::new();
+        webview = webview.with_transparent(true);
+        webview = webview.with_ipc_handler({
+            let ipc_req_tx = self.ipc_req_tx.clone();
+            move |req| {
+                let body = req.into_body().into_bytes();
+                ipc_req_tx.send(body).ignore();
+            }
+        });
+        if window_id != popup::MAIN_WINDOW {
+            webview = webview.with_initialization_script(&format!("window._wongus_window_id = {};", window_id));
+        }
+        webview = webview.with_initialization_script(include_str!("../../setup.js"));
+        webview = webview.with_initialization_script(&self.init_script);
+        webview = webview.with_back_forward_navigation_gestures(false);
+        webview = webview.with_devtools(true);
+
+        // Custom proto: `filex://xPATH`
+        //
+        // 1. to avoid panic due to triple-slash in `file:///`:
+        //    https://github.com/tauri-apps/wry/issues/1255
+        //
+        // 2. to intercept and log errors
+        //
+        // 3. to restrict access to the content root
+        //
+        // 4. to serve from a bundle
+        webview = webview.with_asynchronous_custom_protocol("filex".into(), {
+            let rt = self.rt.clone();
+            let log = self.log.clone();
+            let source = self.filex_source.clone();
+            move |_, request, responder| {
+                serve::serve_file(&rt, &log, &source, request, responder);
+            }
+        });
+
+        // Custom proto: `wongus-file://xPATH`, like `filex` but for explicitly allowed
+        // files outside the content root.
+        webview = webview.with_asynchronous_custom_protocol("wongus-file".into(), {
+            let rt = self.rt.clone();
+            let log = self.log.clone();
+            let source = self.file_source.clone();
+            move |_, request, responder| {
+                serve::serve_file(&rt, &log, &source, request, responder);
+            }
+        });
+
+        // Custom proto: `wongus-proxy://xPATH`, forwards to the server so the page gets
+        // a wongus origin and a placeholder while the server is down
+        if let Some(server_proxy) = &self.server_proxy {
+            webview = webview.with_asynchronous_custom_protocol("wongus-proxy".into(), {
+                let rt = self.rt.clone();
+                let log = self.log.clone();
+                let server_proxy = server_proxy.clone();
+                move |_, request, responder| {
+                    proxy::proxy(&rt, &log, &server_proxy, request, responder);
+                }
+            });
+        }
+        return webview;
+    }
+}
+
+/// Everything a popup's page can have running, to stop when it navigates or
+/// closes. Main thread.
+#[derive(Clone)]
+struct PopupCleanup {
+    popups: Arc<popup::Popups>,
+    clipboard: clipboard::Clipboard,
+    services: Arc<services::Services>,
+    stream_queues: Arc<stream::StreamQueues>,
+    ptys: Arc<pty::Ptys>,
+}
+
+impl PopupCleanup {
+    /// Stop what popup `id`'s page started, for when it navigates.
+    fn clear_popup(&self, id: usize) {
+        self.popups.navigated(id);
+        self.clipboard.clear_window(id);
+        self.services.clear_window(id);
+        self.stream_queues.clear_window(id);
+        self.ptys.kill_window(id);
+    }
+
+    /// Close popup `id` and stop what its page started. Returns the script to tell
+    /// the main page.
+    fn close_popup(&self, id: usize, popup_window: popup::PopupWindow) -> (usize, String) {
+        popup_window.close();
+        self.clear_popup(id);
+        self.popups.remove(id);
+        return (popup::MAIN_WINDOW, format!("window._wongus.popup_closed({});", id));
+    }
+}
+
+fn main() {
+    fn inner() -> Result<(), loga::Error> {
+        let mut argv = env::args();
+        let command = argv.next();
+        let mut argv = argv.collect::<Vec<_>>();
+
+        // Subcommand. Aargvark can't default to the main command, so it's checked for
+        // here (use `./pack` for a content root named `pack`).
+        if argv.first().is_some_and(|a| a == "pack") {
+            argv.remove(0);
+            let args = vark_args::<PackArgs>(command.map(|c| format!("{} pack", c)), argv);
+            return bundle::pack(&args.dir, &args.out);
+        }
+        let args = vark_args::<Args>(command, argv);
+        if args.proxy.is_some() && args.server.is_none() {
+            return Err(loga::err("`--proxy` requires `--server`"));
+        }
+        let log = Log::new_root(if args.debug.is_some() {
+            loga::DEBUG
+        } else {
+            loga::INFO
+        });
+        let content_root =
+            args
+                .content_root
+                .canonicalize()
+                .context_with("Error making content path absolute", ea!(path = args.content_root.dbg_str()))?;
+        let bundle = if content_root.is_file() {
+            Some(bundle::Bundle::load(&content_root)?)
+        } else {
+            None
+        };
+        let config_path = content_root.join("config.json");
+        let config = match &bundle {
+            Some(bundle) => serde_json::from_slice::<Config>(
+                &bundle
+                    .get("config.json")
+                    .context_with("Bundle is missing config.json", ea!(path = content_root.dbg_str()))?,
+            ).context_with("Error parsing bundle config as json", ea!(path = content_root.dbg_str()))?,
+            None => serde_json::from_slice::<Config>(
+                &std::fs::read(
+                    &config_path,
+                ).context_with("Error reading config", ea!(path = config_path.to_string_lossy()))?,
+            ).context_with("Error parsing config as json", ea!(path = config_path.to_string_lossy()))?,
+        };
+        let index_url = match &bundle {
+            Some(_) => "filex://x/index.html".to_string(),
+            None => format!(
+                "filex://x{}",
+                //. PROTO,
+                content_root.join("index.html").to_str().context("Content root path must be utf-8")?
+            ),
+        };
+        let filex_source = Arc::new(match bundle {
+            Some(bundle) => serve::ServeSource::Bundle(bundle),
+            None => serve::ServeSource::Roots(vec![content_root.clone()]),
+        });
+        if config.attach_left && config.attach_right {
+            if config.width.is_some() {
+                return Err(
+                    loga::err(
+                        "Both left and right sides of the window are attached to edges, width cannot be used but it is set in the config (should be null)",
+                    ),
+                );
+            }
+        } else if config.width.is_none() {
+            return Err(
+                loga::err(
+                    "Left or right window edge attachments aren't set so the width is not decided but width is missing from the config",
+                ),
+            );
+        }
+        if config.attach_top && config.attach_bottom {
+            if config.height.is_some() {
+                return Err(
+                    loga::err(
+                        "Both left and right sides of the window are attached to edges, height cannot be used but it is set in the config (should be null)",
+                    ),
+                );
+            }
+        } else if config.height.is_none() {
+            return Err(
+                loga::err(
+                    "Left or right window edge attachments aren't set so the height is not decided but height is missing from the config",
+                ),
+            );
+        }
+
+        // Event loop
+        let mut event_loop = event_loop::EventLoopBuilder::<UserEvent>::with_user_event().build();
+
+        // Window
+        let gtk_window = gtk::ApplicationWindow::new(event_loop.deref().gtk_app());
+        gtk_window.init_layer_shell();
+
+        /// Size the window for `monitor`, fitting `auto` sizes to the page content size
+        /// if it's been reported.
+        fn set_size(
+            gtk_window: &ApplicationWindow,
+            monitor: &gtk::gdk::Monitor,
+            config: &Config,
+            content: Option<popup::IPCSize>,
+        ) {
+            let have_geom = monitor.geometry();
+            shed!{
+                let Some(width) = &config.width else {
+                    break;
+                };
+                if have_geom.width() == 0 {
+                    break;
+                }
+                gtk_window.set_width_request(width.fit_logical_px(MonitorAxis {
+                    logical: have_geom.width(),
+                    mm: monitor.width_mm(),
+                    scale: monitor.scale_factor() as f64,
+                }, content.map(|c| c.width)));
+            };
+            shed!{
+                let Some(height) = &config.height else {
+                    break;
+                };
+                if have_geom.height() == 0 {
+                    break;
+                }
+                gtk_window.set_height_request(height.fit_logical_px(MonitorAxis {
+                    logical: have_geom.height(),
+                    mm: monitor.height_mm(),
+                    scale: monitor.scale_factor() as f64,
+                }, content.map(|c| c.height)));
+            };
+        }
+
+        /// Put the window on the most preferred attached monitor. Returns false if none
+        /// are attached and the window should be hidden.
+        fn set_monitor(
+            log: &Log,
+            gtk_window: &ApplicationWindow,
+            config: &Config,
+            content: Option<popup::IPCSize>,
+        ) -> Result<bool, loga::Error> {
+            let monitor = shed!{
+                'found _;
+                let display = gtk_window.display();
+                let mut monitors = vec![];
+                for i in 0 .. display.n_monitors() {
+                    monitors.push(display.monitor(i).and_downcast::<gtk::gdk::Monitor>().unwrap());
+                }
+                let mut selectors = config.monitors.clone();
+                if selectors.is_empty() {
+                    if let Some(i) = config.monitor_index {
+                        selectors.push(MonitorSelector::Index(i));
+                    }
+                    if let Some(text) = &config.monitor_model {
+                        selectors.push(MonitorSelector::Model(text.clone()));
+                    }
+                }
+
+                // Connector names and descriptions aren't available through gdk, match
+                // outputs from the compositor by position
+                let outputs = if selectors.iter().any(|s| match s {
+                    MonitorSelector::Connector(_) | MonitorSelector::Description(_) => true,
+                    _ => false,
+                }) {
+                    match outputs::query() {
+                        Ok(o) => o,
+                        Err(e) => {
+                            log.log_err(
+                                loga::WARN,
+                                e.context("Error getting monitor connector names and descriptions"),
+                            );
+                            vec![]
+                        },
+                    }
+                } else {
+                    vec![]
+                };
+                let monitor_outputs = monitors.iter().map(|m| {
+                    let geom = m.geometry();
+                    return outputs.iter().find(|o| o.x == geom.x() && o.y == geom.y());
+                }).collect::<Vec<_>>();
+                for selector in &selectors {
+                    match selector {
+                        MonitorSelector::Connector(name) => {
+                            for (m, o) in monitors.iter().zip(&monitor_outputs) {
+                                if o.and_then(|o| o.name.as_ref()) == Some(name) {
+                                    break 'found m.clone();
+                                }
+                            }
+                        },
+                        MonitorSelector::Description(text) => {
+                            for (m, o) in monitors.iter().zip(&monitor_outputs) {
+                                if o
+                                    .and_then(|o| o.description.as_ref())
+                                    .is_some_and(|d| d.to_ascii_lowercase().contains(&text.to_ascii_lowercase())) {
+                                    break 'found m.clone();
+                                }
+                            }
+                        },
+                        MonitorSelector::Model(text) => {
+                            for m in &monitors {
+                                if m
+                                    .model()
+                                    .unwrap_or_default()
+                                    .to_ascii_lowercase()
+                                    .contains(&text.to_ascii_lowercase()) {
+                                    break 'found m.clone();
+                                }
+                            }
+                        },
+                        MonitorSelector::Index(want_i) => {
+                            if let Some(m) = monitors.get(*want_i) {
+                                break 'found m.clone();
+                            }
+                        },
+                        MonitorSelector::Primary => {
+                            if let Some(m) = display.primary_monitor() {
+                                break 'found m;
+                            }
+                        },
+                    }
+                }
+                if !selectors.is_empty() {
+                    match config.on_missing {
+                        OnMissingMonitor::UsePrimary => { },
+                        OnMissingMonitor::Hide => {
+                            log.log(loga::INFO, "None of the configured monitors are attached, hiding window");
+                            return Ok(false);
+                        },
+                        OnMissingMonitor::Exit => {
+                            return Err(loga::err("None of the configured monitors are attached"));
+                        },
+                    }
+                }
+                if let Some(m) = display.primary_monitor() {
+                    break m;
+                }
+                if let Some(m) = monitors.into_iter().next() {
+                    break m;
+                }
+                return Err(loga::err("No suitable monitor found"));
+            };
+            log.log(
+                loga::DEBUG,
+                format!(
+                    "Setting monitor: {:?} {:?} {:?}",
+                    monitor.manufacturer(),
+                    monitor.model(),
+                    monitor.geometry()
+                ),
+            );
+            gtk_window.set_monitor(&monitor);
+            set_size(gtk_window, &monitor, config, content);
+            return Ok(true);
+        }
+
+        // With resizable=false if you don't explicitly set w/h the window never appears
+        gtk_window.set_width_request(1);
+        gtk_window.set_height_request(1);
+
+        // Last page content size reported for `auto` sizes
+        let content_size = Rc::new(Cell::new(None));
+        if !set_monitor(&log, &gtk_window, &config, None)? {
+            match event_loop.create_proxy().send_event(UserEvent::MonitorMissing(true)) {
+                Ok(_) => (),
+                Err(_) => (),
+            };
+        }
+        {
+            // Move to a more preferred monitor when it's attached, or away from the current
+            // one when it's detached
+            let update_monitor = Rc::new({
+                let log = log.clone();
+                let config = config.clone();
+                let window = gtk_window.clone();
+                let event_loop = event_loop.create_proxy();
+                let content_size = content_size.clone();
+                move || {
+                    let event = match set_monitor(&log, &window, &config, content_size.get()) {
+                        Ok(found) => UserEvent::MonitorMissing(!found),
+                        Err(e) => {
+                            if config.on_missing == OnMissingMonitor::Exit {
+                                UserEvent::ErrExit(e)
+                            } else {
+                                log.log_err(loga::WARN, e.context("Error updating monitor selection after monitor change"));
+                                return;
+                            }
+                        },
+                    };
+                    match event_loop.send_event(event) {
+                        Ok(_) => (),
+                        Err(_) => (),
+                    };
+                }
+            });
+            gtk_window.display().connect_monitor_added({
+                let update_monitor = update_monitor.clone();
+                move |_display, _monitor| {
+                    update_monitor();
+                }
+            });
+            gtk_window.display().connect_monitor_removed({
+                let update_monitor = update_monitor.clone();
+                move |_display, _monitor| {
+                    update_monitor();
+                }
+            });
+        }
+        {
+            fn update_screen(window: &ApplicationWindow, screen: &Screen) {
+                if let Some(visual) = screen.rgba_visual() {
+                    window.set_visual(Some(&visual));
+                }
+            }
+
+            // Cb
+            gtk_window.connect_screen_changed({
+                move |window, screen| {
+                    if let Some(screen) = screen {
+                        update_screen(&window, screen);
+                    }
+                }
+            });
+
+            // Immediate
+            if let Some(screen) = GtkWindowExt::screen(&gtk_window) {
+                update_screen(&gtk_window, &screen);
+            }
+        }
+        gtk_window.set_layer(gtk_layer_shell::Layer::Top);
+        gtk_window.auto_exclusive_zone_enable();
+        gtk_window.set_anchor(gtk_layer_shell::Edge::Top, config.attach_top);
+        gtk_window.set_anchor(gtk_layer_shell::Edge::Right, config.attach_right);
+        gtk_window.set_anchor(gtk_layer_shell::Edge::Bottom, config.attach_bottom);
+        gtk_window.set_anchor(gtk_layer_shell::Edge::Left, config.attach_left);
+        gtk_window.set_skip_pager_hint(true);
+        gtk_window.set_deletable(false);
+        let keyboard_mode = config.keyboard_mode.unwrap_or(if config.enable_keyboard {
+            KeyboardMode::Exclusive
+        } else {
+            KeyboardMode::None
+        });
+        gtk_window.set_keyboard_mode(layer_keyboard_mode(keyboard_mode));
+        gtk_window.set_resizable(false);
+        gtk_window.set_app_paintable(true);
+        gtk_window.set_decorated(false);
+        gtk_window.stick();
+        gtk_window.set_title(config.title.as_ref().map(|x| x.as_str()).unwrap_or("This is a wongus"));
+        let default_vbox = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        gtk_window.add(&default_vbox);
+        if config.input_passthrough {
+            gtk_window.input_shape_combine_region(Some(&input_region(vec![])));
+        }
+        gtk_window.show_all();
+        let window = tao::window::Window::new_from_gtk_window(event_loop.deref(), gtk_window).unwrap();
+        window.set_skip_taskbar(true).unwrap();
+        monitors::watch(&window.gtk_window().display(), event_loop.create_proxy());
+
+        // Clicking the main window closes popups. Kept until exit.
+        let _main_press = popup::on_press(window.gtk_window(), {
+            let event_loop = event_loop.create_proxy();
+            move || {
+                match event_loop.send_event(UserEvent::PopupCloseOthers(None)) {
+                    Ok(_) => (),
+                    Err(_) => (),
+                };
+            }
+        });
+
+        // For killing running subprocs
+        let navigated = Arc::new(tokio::sync::Notify::new());
+        let clipboard = clipboard::Clipboard::new(event_loop.create_proxy());
+        let stream_queues = Arc::new(stream::StreamQueues::new(event_loop.create_proxy()));
+        let services =
+            Arc::new(services::Services::new(&log, stream_queues.clone(), config.services.clone())?);
+        let shared_streams = Arc::new(shared_stream::SharedStreams::new(&log, stream_queues.clone()));
+        let ptys = Arc::new(pty::Ptys::new(&log, stream_queues.clone()));
+        let popups = Arc::new(popup::Popups::new());
+        let popup_cleanup = PopupCleanup {
+            popups: popups.clone(),
+            clipboard: clipboard.clone(),
+            services: services.clone(),
+            stream_queues: stream_queues.clone(),
+            ptys: ptys.clone(),
+        };
+
+        // Runtime for async/background processing, driven by a separate thread (below)
+        let rt =
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .context("Error starting ipc processor")?;
+
+        // Webview
+        let (ipc_req_tx, mut ipc_req_rx) = unbounded_channel::<Vec<u8>>();
+        let webview_setup = {
+            let mut script = vec![];
+            for (k, v) in env::vars() {
+                script.push(
+                    format!(
+                        "wongus.env.set({}, {});\n",
+                        serde_json::to_string(&k).unwrap(),
+                        serde_json::to_string(&v).unwrap()
+                    ),
+                );
+            }
+            for kv in &args.args {
+                script.push(
+                    format!(
+                        "wongus.args.set({}, {});\n",
+                        serde_json::to_string(&kv.k).unwrap(),
+                        serde_json::to_string(&kv.v).unwrap()
+                    ),
+                );
+            }
+            let server_proxy = match (&args.server, args.proxy) {
+                (Some(url), Some(_)) => Some(Arc::new(proxy::Proxy::new(url)?)),
+                _ => None,
+            };
+            let mut popup_origins = vec!["filex://x".to_string(), "wongus-file://x".to_string()];
+            if server_proxy.is_some() {
+                popup_origins.push("wongus-proxy://x".to_string());
+            } else if let Some(url) = &args.server {
+                popup_origins.push(popup::origin_of(url).context("Invalid server url")?);
+            }
+            WebviewSetup {
+                rt: rt.handle().clone(),
+                log: log.clone(),
+                ipc_req_tx: ipc_req_tx,
+                init_script: script.join(""),
+                filex_source: filex_source,
+                file_source: Arc::new(
+                    serve::ServeSource::Roots(serve::canonicalize_roots(config.serve_paths.iter().cloned())),
+                ),
+                server_proxy: server_proxy,
+                popup_origins: popup_origins,
+            }
+        };
+        let webview = {
+            let mut webview = webview_setup.builder(popup::MAIN_WINDOW);
+            if config.width.as_ref().is_some_and(|w| w.is_auto()) ||
+                config.height.as_ref().is_some_and(|h| h.is_auto()) {
+                webview = webview.with_initialization_script("window._wongus_auto_size = true;");
+            }
+            if let Some(server_proxy) = &webview_setup.server_proxy {
+                webview = webview.with_url(server_proxy.start_url());
+            } else if let Some(url) = &args.server {
+                webview = webview.with_url(url);
+            } else {
+                webview = webview.with_url(index_url);
+            }
+            webview = webview.with_on_page_load_handler({
+                let navigated = navigated.clone();
+                let clipboard = clipboard.clone();
+                let services = services.clone();
+                let stream_queues = stream_queues.clone();
+                let ptys = ptys.clone();
+                let event_loop = event_loop.create_proxy();
+                move |ev, _| {
+                    let PageLoadEvent::Started = ev else {
+                        return;
+                    };
+                    navigated.notify_waiters();
+                    clipboard.clear_watchers();
+                    services.clear_subscribers();
+                    stream_queues.clear();
+                    ptys.kill_all();
+                    match event_loop.send_event(UserEvent::PopupCloseAll) {
+                        Ok(_) => (),
+                        Err(_) => (),
+                    };
+                }
+            });
+            webview.build_gtk(&default_vbox).context("Error initializing webview")?
+        };
+
+        // Persistent key-value storage for the page
+        let storage = Arc::new(storage::Storage::new(&config, &content_root));
+
+        // Services run independently of the page, on the background runtime
+        services.start(rt.handle());
+        let rt_handle = rt.handle().clone();
+        let stop_services = services.clone();
+
+        // Sleep, session lock, and clock events for the page. Not fatal, logind may not
+        // be available.
+        rt.spawn({
+            let log = log.fork(ea!(sys = "system_events"));
+            let event_loop = event_loop.create_proxy();
+            async move {
+                match system_events::watch(event_loop).await {
+                    Ok(_) => { },
+                    Err(e) => {
+                        log.log_err(loga::WARN, e.context("Error watching system events, events won't be sent"));
+                    },
+                }
+            }
+        });
+
+        let hide_when_locked = config.hide_when_locked;
+        let size_config = config.clone();
+        let popup_edges = popup::ParentEdges {
+            top: config.attach_top,
+            right: config.attach_right,
+            bottom: config.attach_bottom,
+            left: config.attach_left,
+        };
+
+        // For killing thread when program exits
+        let exited = Arc::new(tokio::sync::Notify::new());
+
+        // Start thread for async/background processing (ipc, subcommands)
+        spawn({
+            let exited = exited.clone();
+            let event_loop = event_loop.create_proxy();
+            let log = log.clone();
+            let stream_queues = stream_queues.clone();
+            let ptys = ptys.clone();
+            let popups = popups.clone();
+
+            // Handle ipc requests via js
+            let window_ipc = {
+                let log = log.fork(ea!(ipc = "window"));
+                let event_loop = event_loop.clone();
+                async move {
+                    while let Some(req) = ipc_req_rx.recv().await {
+                        let req = match serde_json::from_slice::<WindowIpc>(&req) {
+                            Ok(r) => r,
+                            Err(e) => {
+                                log.log_err(
+                                    loga::WARN,
+                                    e.context_with(
+                                        "Assertion! Error parsing IPC request",
+                                        ea!(req = String::from_utf8_lossy(&req)),
+                                    ),
+                                );
+                                return;
+                            },
+                        };
+                        let window = popup::window_of(req.id);
+
+                        // Requests from a popup end when it closes rather than when the main page
+                        // navigates
+                        let navigated = if window == popup::MAIN_WINDOW {
+                            navigated.clone()
+                        } else {
+                            match popups.closed(window) {
+                                Some(closed) => closed,
+                                None => {
+                                    log.log_with(
+                                        loga::DEBUG,
+                                        "Dropping IPC request from closed popup",
+                                        ea!(window = window),
+                                    );
+                                    match event_loop.send_event(
+                                        UserEvent::Script(
+                                            window,
+                                            format!(
+                                                "(window._wongus.responses.get({}))({});",
+                                                req.id,
+                                                serde_json::to_string(&json!({
+                                                    "err": "Popup closed"
+                                                })).unwrap()
+                                            ),
+                                        ),
+                                    ) {
+                                        Ok(_) => (),
+                                        Err(_) => (),
+                                    };
+                                    continue;
+                                },
+                            }
+                        };
+                        tokio::spawn({
+                            let ipc_resp = event_loop.clone();
+                            let log = log.clone();
+                            let storage = storage.clone();
+                            let services = services.clone();
+                            let shared_streams = shared_streams.clone();
+                            let stream_queues = stream_queues.clone();
+                            let ptys = ptys.clone();
+                            async move {
+                                let resp = match async {
+                                    match req.body {
+                                        IPCReqBody::Log(message) => {
+                                            log.log(loga::INFO, format!("wongus.log: {}", message));
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::ListDir(path) => {
+                                            let mut entries =
+                                                read_dir(&path)
+                                                    .await
+                                                    .context_with(
+                                                        "Error listing directory",
+                                                        ea!(path = path.dbg_str()),
+                                                    )?;
+                                            let mut out = vec![];
+                                            while let Some(entry) =
+                                                entries
+                                                    .next_entry()
+                                                    .await
+                                                    .context("Error reading directory entries")? {
+                                                // Non-utf-8 paths are returned lossily, `list_dir_stat` has the exact
+                                                // bytes
+                                                out.push(entry.path().to_string_lossy().to_string());
+                                            }
+                                            return Ok(serde_json::to_value(&out).unwrap());
+                                        },
+                                        IPCReqBody::ListDirStat(req) => {
+                                            let pattern = match &req.glob {
+                                                Some(g) => Some(
+                                                    glob::Pattern::new(
+                                                        g,
+                                                    ).context_with("Invalid glob pattern", ea!(glob = g))?,
+                                                ),
+                                                None => None,
+                                            };
+                                            return Ok(
+                                                serde_json::to_value(
+                                                    &files::list_dir_stat(
+                                                        &req.path,
+                                                        req.depth.unwrap_or(1),
+                                                        pattern.as_ref(),
+                                                    ).await?,
+                                                ).unwrap(),
+                                            );
+                                        },
+                                        IPCReqBody::FileExists(path) => {
+                                            return Ok(json!(path.exists()));
+                                        },
+                                        IPCReqBody::Stat(path) => {
+                                            return Ok(serde_json::to_value(&files::stat(&path).await?).unwrap());
+                                        },
+                                        IPCReqBody::Read(path) => {
+                                            return Ok(
+                                                json!(
+                                                    &String::from_utf8(
+                                                        tokio::fs::read(&path)
+                                                            .await
+                                                            .context("Error performing read command")?,
+                                                    ).context("File isn't valid utf-8")?
+                                                ),
+                                            );
+                                        },
+                                        IPCReqBody::ReadBytes(req) => {
+                                            return Ok(
+                                                json!(
+                                                    BASE64_STANDARD.encode(
+                                                        files::read_range(
+                                                            &req.path,
+                                                            req.offset.unwrap_or(0),
+                                                            req.length,
+                                                        ).await?,
+                                                    )
+                                                ),
+                                            );
+                                        },
+                                        IPCReqBody::ReadTail(req) => {
+                                            return Ok(json!(files::read_tail(&req.path, req.lines).await?));
+                                        },
+                                        IPCReqBody::Write(req) => {
+                                            files::write_atomic(&req.path, &req.data.into_bytes()?).await?;
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::Append(req) => {
+                                            files::append(&req.path, &req.data.into_bytes()?).await?;
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::CreateDir(path) => {
+                                            tokio::fs::create_dir_all(&path)
+                                                .await
+                                                .context_with("Error creating directory", ea!(path = path.dbg_str()))?;
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::Remove(req) => {
+                                            files::remove(&req.path, req.recursive).await?;
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::Rename(req) => {
+                                            tokio::fs::rename(&req.from, &req.to)
+                                                .await
+                                                .context_with(
+                                                    "Error renaming file",
+                                                    ea!(from = req.from.dbg_str(), to = req.to.dbg_str()),
+                                                )?;
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::BacklightList => {
+                                            return Ok(serde_json::to_value(&backlight::list(&log).await?).unwrap());
+                                        },
+                                        IPCReqBody::BacklightGet(name) => {
+                                            return Ok(serde_json::to_value(&backlight::get(&name).await?).unwrap());
+                                        },
+                                        IPCReqBody::BacklightSet(req) => {
+                                            let raw = match (req.percent, req.raw) {
+                                                (Some(percent), None) => {
+                                                    backlight::percent_to_raw(
+                                                        percent,
+                                                        backlight::get(&req.name).await?.max_brightness,
+                                                    )
+                                                },
+                                                (None, Some(raw)) => raw,
+                                                _ => {
+                                                    return Err(loga::err("Exactly one of `percent` or `raw` must be set"));
+                                                },
+                                            };
+                                            backlight::set(&req.name, raw).await?;
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::BacklightWatch(req) => {
+                                            tokio::spawn({
+                                                let ipc_resp = ipc_resp.clone();
+                                                let log = log.fork(ea!(backlight = &req.name));
+                                                async move {
+                                                    let work = backlight::watch(&req.name, |state| {
+                                                        match ipc_resp.send_event(
+                                                            UserEvent::Script(
+                                                                window,
+                                                                format!(
+                                                                    "(window._wongus.stream_cbs.get({}))({});",
+                                                                    req.id,
+                                                                    serde_json::to_string(&state).unwrap()
+                                                                ),
+                                                            ),
+                                                        ) {
+                                                            Ok(_) => (),
+                                                            Err(_) => (),
+                                                        };
+                                                    });
+                                                    select!{
+                                                        _ = navigated.notified() => {
+                                                        },
+                                                        r = work => {
+                                                            if let Err(e) = r {
+                                                                log.log_err(loga::WARN, e.context("Error watching backlight"));
+                                                            }
+                                                        }
+                                                    }
+                                                }
+                                            });
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::NetworkGet => {
+                                            return Ok(serde_json::to_value(&network::get().await?).unwrap());
+                                        },
+                                        IPCReqBody::NetworkWatch(req) => {
+                                            let refresh = match req.refresh_secs {
+                                                Some(secs) => {
+                                                    if !(secs > 0.) {
+                                                        return Err(
+                                                            loga::err_with(
+                                                                "refresh_secs must be positive",
+                                                                ea!(refresh_secs = secs.to_string()),
+                                                            ),
+                                                        );
+                                                    }
+                                                    Some(
+                                                        Duration::try_from_secs_f64(secs)
+                                                            .context_with(
+                                                                "Invalid refresh_secs",
+                                                                ea!(refresh_secs = secs.to_string()),
+                                                            )?
+                                                            .max(network::MIN_REFRESH),
+                                                    )
+                                                },
+                                                None => None,
+                                            };
+                                            tokio::spawn({
+                                                let ipc_resp = ipc_resp.clone();
+                                                let log = log.clone();
+                                                async move {
+                                                    let work =
+                                                        network::watch(
+                                                            refresh,
+                                                            |state| {
+                                                                match ipc_resp.send_event(
+                                                                    UserEvent::Script(
+                                                                        window,
+                                                                        format!(
+                                                                            "(window._wongus.stream_cbs.get({}))({});",
+                                                                            req.id,
+                                                                            serde_json::to_string(&state).unwrap()
+                                                                        ),
+                                                                    ),
+                                                                ) {
+                                                                    Ok(_) => (),
+                                                                    Err(_) => (),
+                                                                };
+                                                            },
+                                                        );
+                                                    select!{
+                                                        _ = navigated.notified() => {
+                                                        },
+                                                        r = work => {
+                                                            if let Err(e) = r {
+                                                                log.log_err(loga::WARN, e.context("Error watching network"));
+                                                            }
+                                                        }
+                                                    }
+                                                }
+                                            });
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::Clipboard(req) => {
+                                            let (resp_tx, resp_rx) = oneshot::channel();
+                                            ipc_resp
+                                                .send_event(UserEvent::Clipboard(req, resp_tx))
+                                                .map_err(|_| loga::err("Event loop has exited"))?;
+                                            return resp_rx.await.context("Main thread dropped clipboard request")?;
+                                        },
+                                        IPCReqBody::StorageGet(key) => {
+                                            return Ok(json!(storage.get(&key).await?));
+                                        },
+                                        IPCReqBody::StorageSet(req) => {
+                                            storage.set(req.key, req.value).await?;
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::StorageDelete(key) => {
+                                            storage.delete(&key).await?;
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::StorageList => {
+                                            return Ok(json!(storage.list().await?));
+                                        },
+                                        IPCReqBody::PtyWrite(req) => {
+                                            ptys.write(req.id, req.data.into_bytes()?)?;
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::PtyResize(req) => {
+                                            ptys.resize(req.id, req.size)?;
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::Schedule(req) => {
+                                            let job = schedule::Schedule::new(req)?;
+                                            tokio::spawn({
+                                                let stream_queues = stream_queues.clone();
+                                                let navigated = navigated.clone();
+                                                let log = log.clone();
+                                                async move {
+                                                    select!{
+                                                        _ = navigated.notified() => {
+                                                        },
+                                                        r = job.run(stream_queues) => {
+                                                            if let Err(e) = r {
+                                                                log.log_err(loga::WARN, e.context("Error running schedule"));
+                                                            }
+                                                        },
+                                                    }
+                                                }
+                                            });
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::ServicesSubscribe(req) => {
+                                            services.subscribe(&req.name, req.id)?;
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::PopupOpen(req) => {
+                                            if window != popup::MAIN_WINDOW {
+                                                return Err(loga::err("Popups can only be opened from the main page"));
+                                            }
+                                            let (resp_tx, resp_rx) = oneshot::channel();
+                                            ipc_resp
+                                                .send_event(UserEvent::PopupOpen(req, resp_tx))
+                                                .map_err(|_| loga::err("Event loop has exited"))?;
+                                            return resp_rx.await.context("Main thread dropped popup request")?;
+                                        },
+                                        IPCReqBody::WindowVisibility(change) => {
+                                            ipc_resp
+                                                .send_event(UserEvent::WindowVisibility(change))
+                                                .map_err(|_| loga::err("Event loop has exited"))?;
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::WindowSetInputRegion(rects) => {
+                                            ipc_resp
+                                                .send_event(UserEvent::WindowSetInputRegion(rects))
+                                                .map_err(|_| loga::err("Event loop has exited"))?;
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::Monitors => {
+                                            let (resp_tx, resp_rx) = oneshot::channel();
+                                            ipc_resp
+                                                .send_event(UserEvent::Monitors(false, resp_tx))
+                                                .map_err(|_| loga::err("Event loop has exited"))?;
+                                            return Ok(resp_rx.await.context("Main thread dropped monitors request")?);
+                                        },
+                                        IPCReqBody::CurrentMonitor => {
+                                            let (resp_tx, resp_rx) = oneshot::channel();
+                                            ipc_resp
+                                                .send_event(UserEvent::Monitors(true, resp_tx))
+                                                .map_err(|_| loga::err("Event loop has exited"))?;
+                                            return Ok(resp_rx.await.context("Main thread dropped monitors request")?);
+                                        },
+                                        IPCReqBody::WindowGrabKeyboard => {
+                                            ipc_resp
+                                                .send_event(UserEvent::WindowGrabKeyboard(true))
+                                                .map_err(|_| loga::err("Event loop has exited"))?;
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::WindowReleaseKeyboard => {
+                                            ipc_resp
+                                                .send_event(UserEvent::WindowGrabKeyboard(false))
+                                                .map_err(|_| loga::err("Event loop has exited"))?;
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::WindowContentSize(size) => {
+                                            if window != popup::MAIN_WINDOW {
+                                                return Err(loga::err("Only the main page sizes the window"));
+                                            }
+                                            ipc_resp
+                                                .send_event(UserEvent::WindowContentSize(size))
+                                                .map_err(|_| loga::err("Event loop has exited"))?;
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::PopupClose(id) => {
+                                            if window != popup::MAIN_WINDOW && window != id {
+                                                return Err(
+                                                    loga::err("Popups can only be closed by the main page or themselves"),
+                                                );
+                                            }
+                                            ipc_resp
+                                                .send_event(UserEvent::PopupClose(id))
+                                                .map_err(|_| loga::err("Event loop has exited"))?;
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::PopupPost(req) => {
+                                            if (window == popup::MAIN_WINDOW) == (req.to == popup::MAIN_WINDOW) {
+                                                return Err(
+                                                    loga::err("Messages can only be sent between a popup and the main page"),
+                                                );
+                                            }
+                                            ipc_resp
+                                                .send_event(
+                                                    UserEvent::Script(
+                                                        req.to,
+                                                        format!(
+                                                            "window._wongus.popup_message({}, {});",
+                                                            window,
+                                                            serde_json::to_string(&req.message).unwrap()
+                                                        ),
+                                                    ),
+                                                )
+                                                .map_err(|_| loga::err("Event loop has exited"))?;
+                                            return Ok(json!({ }));
+                                        },
+                                        IPCReqBody::RunCommand(req) => {
+                                            if req.command.is_empty() {
+                                                return Err(loga::err("Commandline is empty"));
+                                            }
+                                            let mut command = Command::new(&req.command[0]);
+                                            command.args(&req.command[1..]);
+                                            if let Some(cwd) = req.working_dir {
+                                                command.current_dir(&cwd);
+                                            }
+                                            for (k, v) in req.environment {
+                                                command.env(k, v);
+                                            }
+                                            let log = Log::new().fork(ea!(command = command.dbg_str()));
+                                            let res = select!{
+                                                res = command.output() => res,
+                                                _ = navigated.notified() => {
+                                                    return Err(loga::err("Navigation occurred"));
+                                                },
+                                                _ = sleep(Duration::from_secs(req.timeout_secs.unwrap_or(10))) => {
+                                                    return Err(
+                                                        loga::err("Command execution duration exceeded timeout"),
+                                                    );
+                                                }
+                                            }.stack_context(&log, "Error starting command")?;
+                                            let log = log.fork(ea!(output = res.dbg_str()));
+                                            if !res.status.success() {
+                                                return Err(log.err("Command exited with unsuccessful status"));
+                                            }
+                                            let stdout =
+                                                String::from_utf8(
+                                                    res.stdout,
+                                                ).stack_context(&log, "stdout was not valid utf-8")?;
+                                            let stderr =
+                                                String::from_utf8(
+                                                    res.stderr,
+                                                ).stack_context(&log, "stderr was not valid utf-8")?;
+                                            return Ok(json!({
+                                                "stdout": stdout,
+                                                "stderr": stderr
+                                            }));
+                                        },
+                                        IPCReqBody::RunDetachedCommand(req) => {
+                                            if req.command.is_empty() {
+                                                return Err(loga::err("Commandline is empty"));
+                                            }
+                                            let mut command = Command::new(&req.command[0]);
+                                            command.args(&req.command[1..]);
+                                            if let Some(cwd) = req.working_dir {
+                                                command.current_dir(&cwd);
+                                            }
+                                            for (k, v) in req.environment {
+                                                command.env(k, v);
+                                            }
+                                            let pid =
+                                                command
+                                                    .spawn()
+                                                    .context_with(
+                                                        "Error starting command",
+                                                        ea!(command = command.dbg_str()),
+                                                    )?
+                                                    .id();
+                                            return Ok(json!({
+                                                "pid": pid
+                                            }));
+                                        },
+                                        IPCReqBody::StreamCommand(req) => {
+                                            if let Some(size) = req.pty {
+                                                if req.shared {
+                                                    return Err(loga::err("Pty commands can't be shared"));
+                                                }
+
+                                                // Dropping raw chunks would corrupt escape sequences
+                                                stream_queues.configure(req.id, None, stream::OverflowPolicy::Unbounded);
+                                                ptys.spawn(
+                                                    req.id,
+                                                    &req.command,
+                                                    req.working_dir,
+                                                    req.environment,
+                                                    size,
+                                                )?;
+                                                return Ok(json!({ }));
+                                            }
+                                            stream_queues.configure(req.id, req.queue_limit, req.overflow);
+                                            if req.shared {
+                                                if req.batch_ms.is_some() {
+                                                    return Err(
+                                                        loga::err("`batch_ms` isn't supported for shared streams"),
+                                                    );
+                                                }
+                                                shared_streams.subscribe(
+                                                    shared_stream::SharedStreamKey {
+                                                        command: req.command,
+                                                        working_dir: req.working_dir,
+                                                        environment: req.environment.into_iter().collect(),
+                                                        mode: req.mode,
+                                                    },
+                                                    req.id,
+                                                    req.error_id,
+                                                    req.replay.unwrap_or(0),
+                                                    navigated.clone(),
+                                                ).await?;
+                                                return Ok(json!({ }));
+                                            }
+                                            tokio::spawn({
+                                                let ipc_resp = ipc_resp.clone();
+                                                let stream_queues = stream_queues.clone();
+                                                if req.command.is_empty() {
+                                                    return Err(loga::err("Commandline is empty"));
+                                                }
+                                                let mut command = Command::new(&req.command[0]);
+                                                command.stdout(std::process::Stdio::piped());
+                                                command.args(&req.command[1..]);
+                                                if let Some(cwd) = req.working_dir {
+                                                    command.current_dir(&cwd);
+                                                }
+                                                for (k, v) in req.environment {
+                                                    command.env(k, v);
+                                                }
+                                                let log = Log::new().fork(ea!(command = command.dbg_str()));
+                                                let mut proc =
+                                                    command.spawn().stack_context(&log, "Error starting command")?;
+                                                async move {
+                                                    let work =
+                                                        stream::deliver(
+                                                            stream::StreamReader::new(
+                                                                proc.stdout.take().unwrap(),
+                                                                req.mode,
+                                                            ),
+                                                            &stream_queues,
+                                                            req.id,
+                                                            req.error_id,
+                                                            req.batch_ms.map(Duration::from_millis),
+                                                        );
+                                                    let do_log = |level, m| {
+                                                        log.log(level, &m);
+                                                        match ipc_resp.send_event(
+                                                            UserEvent::Script(
+                                                                window,
+                                                                format!(
+                                                                    "console.log({});",
+                                                                    serde_json::to_string(&m).unwrap()
+                                                                ),
+                                                            ),
+                                                        ) {
+                                                            Ok(_) => (),
+                                                            Err(_) => (),
+                                                        }
+                                                    };
+                                                    match select!{
+                                                        _ = navigated.notified() => {
+                                                            Err(loga::err("Navigation occurred"))
+                                                        },
+                                                        w = work => {
+                                                            w
+                                                        }
+                                                    } {
+                                                        Ok(_) => {
+                                                            do_log(
+                                                                loga::INFO,
+                                                                format!(
+                                                                    "Streaming command [{:?}] exited normally",
+                                                                    command
+                                                                ),
+                                                            );
+                                                        },
+                                                        Err(e) => {
+                                                            do_log(
+                                                                loga::WARN,
+                                                                format!(
+                                                                    "Streaming command [{:?}] failed with error: {}",
+                                                                    command,
+                                                                    e
+                                                                ),
+                                                            );
+                                                        },
+                                                    }
+                                                }
+                                            });
+                                            return Ok(json!({ }));
+                                        },
+                                    }
+                                }.await {
+                                    Ok(r) => r,
+                                    Err(e) => {
+                                        let out = json!({
+                                            "err": e.to_string()
+                                        });
+                                        log.log_err(loga::DEBUG, e.context("Error processing IPC message"));
+                                        out
+                                    },
+                                };
+                                match ipc_resp.send_event(
+                                    UserEvent::Script(
+                                        window,
+                                        format!(
+                                            "(window._wongus.responses.get({}))({});",
+                                            req.id,
+                                            serde_json::to_string(&resp).unwrap()
+                                        ),
+                                    ),
+                                ) {
+                                    Ok(_) => { },
+                                    Err(e) => {
+                                        log.log_err(
+                                            loga::DEBUG,
+                                            loga::err(e).context("Error sending IPC response"),
+                                        );
+                                    },
+                                };
+                            }
+                        });
+                    }
+                }
+            };
+
+            // Handle requests from curl via uds
+            let external_ipc = {
+                struct State {
+                    log: loga::Log,
+                    ipc_resp: EventLoopProxy<UserEvent>,
+                    ids: AtomicUsize,
+                }
+
+                let state = Arc::new(State {
+                    log: log.fork(ea!(ipc = "external")),
+                    ipc_resp: event_loop.clone(),
+                    ids: AtomicUsize::new(1),
+                });
+                async move {
+                    if let Some(listen) = config.listen {
+                        async fn handle_req(
+                            state: Arc<State>,
+                            req: Request<Incoming>,
+                        ) -> Result<Response<Body>, Infallible> {
+                            let id = state.ids.fetch_add(1, Ordering::Relaxed);
+                            let (res_tx, res_rx) = oneshot::channel();
+                            let req =
+                                match req
+                                    .into_body()
+                                    .collect()
+                                    .await
+                                    .context("Error reading request body")
+                                    .and_then(
+                                        |r| serde_json::from_slice::<serde_json::Value>(
+                                            &r.to_bytes(),
+                                        ).context("Error parsing request body as JSON"),
+                                    ) {
+                                    Ok(r) => r,
+                                    Err(e) => {
+                                        return Ok(response_400(e));
+                                    },
+                                };
+                            match state
+                                .ipc_resp
+                                .send_event(
+                                    UserEvent::ExternalScript(
+                                        format!(
+                                            "return window._wongus.external_ipc({}, {});",
+                                            id,
+                                            serde_json::to_string(&req).unwrap()
+                                        ),
+                                        res_tx,
+                                    ),
+                                ) {
+                                Ok(_) => { },
+                                Err(_) => {
+                                    return Ok(response_503());
+                                },
+                            };
+                            match res_rx.await {
+                                Ok(r) => {
+                                    match r {
+                                        ExternalIpcResp::Ok(v) => {
+                                            return Ok(response_200_json(v));
+                                        },
+                                        ExternalIpcResp::Err(v) => {
+                                            return Ok(response_503_text(v));
+                                        },
+                                    }
+                                },
+                                Err(e) => {
+                                    state
+                                        .log
+                                        .log_err(loga::WARN, e.context("External ipc request to window failed"));
+                                    return Ok(response_503());
+                                },
+                            }
+                        }
+
+                        remove_file(
+                            &listen,
+                        ).log_with(
+                            &state.log,
+                            loga::WARN,
+                            "Failed to clean up old usd socket",
+                            ea!(path = listen.to_string_lossy()),
+                        );
+                        let listener = UnixSocket::new_stream()?;
+                        listener.bind(&listen).stack_context(&state.log, "Error binding to uds socket address")?;
+                        let listener = listener.listen(10).context("Error starting to listen on uds socket")?;
+                        while let Some((conn, _)) = listener.accept().await.ok() {
+                            tokio::spawn({
+                                let state = state.clone();
+                                async move {
+                                    match async {
+                                        hyper_util::server::conn::auto::Builder::new(
+                                            hyper_util::rt::TokioExecutor::new(),
+                                        )
+                                            .serve_connection(
+                                                hyper_util::rt::TokioIo::new(conn),
+                                                hyper::service::service_fn({
+                                                    let state = state.clone();
+                                                    move |req| handle_req(state.clone(), req)
+                                                }),
+                                            )
+                                            .await
+                                            .map_err(
+                                                |e| loga::err_with(
+                                                    "Error serving HTTP on connection",
+                                                    ea!(err = e.to_string()),
+                                                ),
+                                            )?;
+                                        return Ok(()) as Result<(), loga::Error>;
+                                    }.await {
+                                        Ok(_) => (),
+                                        Err(e) => {
+                                            state.log.log_err(loga::DEBUG, e.context("Error serving connection"));
+                                        },
+                                    }
+                                }
+                            });
+                        }
+                    } else {
+                        std::future::pending::<()>().await;
+                    }
+                    return Ok(()) as Result<(), loga::Error>;
+                }
+            };
+
+            // SIGUSR1 toggles the window, SIGUSR2 shows it
+            let signals = {
+                let event_loop = event_loop.clone();
+                let log = log.clone();
+                async move {
+                    let (mut usr1, mut usr2) =
+                        match (signal(SignalKind::user_defined1()), signal(SignalKind::user_defined2())) {
+                            (Ok(usr1), Ok(usr2)) => (usr1, usr2),
+                            (Err(e), _) | (_, Err(e)) => {
+                                log.log_err(
+                                    loga::WARN,
+                                    e.context("Error listening for SIGUSR1/SIGUSR2, they'll be ignored"),
+                                );
+                                std::future::pending::<()>().await;
+                                return;
+                            },
+                        };
+                    loop {
+                        let change = select!{
+                            _ = usr1.recv() => WindowVisibility::Toggle,
+                            _ = usr2.recv() => WindowVisibility::Show,
+                        };
+                        match event_loop.send_event(UserEvent::WindowVisibility(change)) {
+                            Ok(_) => (),
+                            Err(_) => (),
+                        };
+                    }
+                }
+            };
+
+            // Keep thread alive while stuff's going on
+            move || rt.block_on(async move {
+                select!{
+                    _ = exited.notified() => {
+                    },
+                    r = external_ipc => match r {
+                        Ok(_) => {
+                            log.log(loga::WARN, "Subservice handle external IPC exited!");
+                        },
+                        Err(e) => {
+                            match event_loop.send_event(
+                                UserEvent::ErrExit(e.context("Subservice handling external IPC exited with error")),
+                            ) {
+                                Ok(_) => { },
+                                Err(_) => { },
+                            };
+                        }
+                    },
+                    _ = window_ipc => {
+                        log.log(loga::WARN, "Window async IPC task exited!");
+                    },
+                    _ = signals => {
+                    },
+                }
+            })
+        });
+
+        // Run event loop in main thread
+        let err = Arc::new(Mutex::new(None));
+        event_loop.run_return({
+            let err = err.clone();
+
+            // Scripts are collected and run together once all pending events are handled,
+            // to reduce the per-script overhead when there are many
+            let mut scripts = vec![];
+            let mut popup_windows = HashMap::<usize, popup::PopupWindow>::new();
+
+            // Hidden with `wongus.window` or a signal
+            let mut hidden = false;
+            let mut session_locked = false;
+            let mut monitor_missing = false;
+            let event_loop = event_loop.create_proxy();
+            move |event, target, control_flow| {
+                *control_flow = event_loop::ControlFlow::Wait;
+                match event {
+                    Event::UserEvent(e) => {
+                        match e {
+                            UserEvent::Script(window, script) => {
+                                scripts.push((window, script));
+                            },
+                            UserEvent::FlushStreams => {
+                                scripts.extend(stream_queues.drain(&log));
+                            },
+                            UserEvent::ExternalScript(script, resp) => {
+                                match webview.evaluate_script_with_callback(&script, {
+                                    let resp = RefCell::new(Some(resp));
+                                    move |json| {
+                                        resp
+                                            .borrow_mut()
+                                            .take()
+                                            .unwrap()
+                                            .send(serde_json::from_str(&json).unwrap())
+                                            .map_err(|_| loga::err(""))
+                                            .ignore();
+                                    }
+                                }) {
+                                    Ok(_) => { },
+                                    Err(e) => {
+                                        log.log_err(loga::WARN, e.context("Error executing external ipc cb"));
+                                    },
+                                };
+                            },
+                            UserEvent::Clipboard(req, resp) => {
+                                clipboard.handle(req, resp);
+                            },
+                            UserEvent::SessionLocked(locked) => {
+                                session_locked = locked;
+                                window.set_visible(
+                                    !hidden && !monitor_missing && !(hide_when_locked && session_locked),
+                                );
+                            },
+                            UserEvent::MonitorMissing(missing) => {
+                                monitor_missing = missing;
+                                window.set_visible(
+                                    !hidden && !monitor_missing && !(hide_when_locked && session_locked),
+                                );
+                            },
+                            UserEvent::WindowVisibility(change) => {
+                                hidden = match change {
+                                    WindowVisibility::Hide => true,
+                                    WindowVisibility::Show => false,
+                                    WindowVisibility::Toggle => !hidden,
+                                };
+
+                                // Popups would be left floating beside nothing
+                                if hidden {
+                                    for (id, p) in popup_windows.drain() {
+                                        scripts.push(popup_cleanup.close_popup(id, p));
+                                    }
+                                }
+
+                                // Hiding unmaps the layer surface, releasing its exclusive zone
+                                window.set_visible(
+                                    !hidden && !monitor_missing && !(hide_when_locked && session_locked),
+                                );
+                            },
+                            UserEvent::WindowSetInputRegion(rects) => {
+                                window.gtk_window().input_shape_combine_region(rects.map(input_region).as_ref());
+                            },
+                            UserEvent::Monitors(current, resp) => {
+                                let monitors = if current {
+                                    monitors::current(window.gtk_window())
+                                } else {
+                                    monitors::list(&window.gtk_window().display())
+                                };
+                                match resp.send(monitors) {
+                                    Ok(_) => (),
+                                    Err(_) => (),
+                                };
+                            },
+                            UserEvent::WindowGrabKeyboard(grab) => {
+                                window.gtk_window().set_keyboard_mode(layer_keyboard_mode(if grab {
+                                    KeyboardMode::Exclusive
+                                } else if keyboard_mode == KeyboardMode::Exclusive {
+                                    // Releasing would otherwise do nothing
+                                    KeyboardMode::OnDemand
+                                } else {
+                                    keyboard_mode
+                                }));
+                            },
+                            UserEvent::WindowContentSize(size) => shed!{
+                                if !(size.width >= 0. && size.height >= 0.) {
+                                    break;
+                                }
+                                content_size.set(Some(size));
+                                let gtk_window = window.gtk_window();
+                                let Some(monitor) =
+                                    gtk_window
+                                        .window()
+                                        .and_then(|w| gtk_window.display().monitor_at_window(&w)) else {
+                                        break;
+                                    };
+                                set_size(gtk_window, &monitor, &size_config, Some(size));
+                            },
+                            UserEvent::PopupOpen(req, resp) => {
+                                let id = popups.add();
+                                let webview = webview_setup.builder(id).with_on_page_load_handler({
+                                    let popup_cleanup = popup_cleanup.clone();
+                                    move |ev, _| {
+                                        let PageLoadEvent::Started = ev else {
+                                            return;
+                                        };
+                                        popup_cleanup.clear_popup(id);
+                                    }
+                                });
+                                let res =
+                                    popup::open(
+                                        target.gtk_app(),
+                                        window.gtk_window(),
+                                        &popup_edges,
+                                        event_loop.clone(),
+                                        id,
+                                        req,
+                                        &webview_setup.popup_origins,
+                                        webview,
+                                    );
+                                match res {
+                                    Ok(p) => {
+                                        popup_windows.insert(id, p);
+                                        match resp.send(Ok(json!({
+                                            "id": id
+                                        }))) {
+                                            Ok(_) => (),
+                                            Err(_) => (),
+                                        };
+                                    },
+                                    Err(e) => {
+                                        popups.remove(id);
+                                        match resp.send(Err(e)) {
+                                            Ok(_) => (),
+                                            Err(_) => (),
+                                        };
+                                    },
+                                }
+                            },
+                            UserEvent::PopupClose(id) => {
+                                if let Some(p) = popup_windows.remove(&id) {
+                                    scripts.push(popup_cleanup.close_popup(id, p));
+                                }
+                            },
+                            UserEvent::PopupCloseOthers(keep) => {
+                                let ids =
+                                    popup_windows.keys().copied().filter(|id| Some(*id) != keep).collect::<Vec<_>>();
+                                for id in ids {
+                                    let p = popup_windows.remove(&id).unwrap();
+                                    scripts.push(popup_cleanup.close_popup(id, p));
+                                }
+                            },
+                            UserEvent::PopupCloseAll => {
+                                // The main page navigated, so there's nobody to tell
+                                for (id, p) in popup_windows.drain() {
+                                    popup_cleanup.close_popup(id, p);
+                                }
+                            },
+                            UserEvent::ErrExit(e) => {
+                                *err.lock().unwrap() = Some(e);
+                                *control_flow = ControlFlow::Exit;
+                            },
+                        }
+                    },
+                    Event::WindowEvent { event, .. } => {
+                        match event {
+                            WindowEvent::CloseRequested | WindowEvent::Destroyed => {
+                                *control_flow = ControlFlow::Exit;
+                            },
+                            _ => { },
+                        }
+                    },
+                    Event::MainEventsCleared => {
+                        if scripts.is_empty() {
+                            return;
+                        }
+
+                        // Isolate scripts so one failing doesn't prevent the rest from running
+                        let mut window_scripts = HashMap::<usize, String>::new();
+                        for (window, s) in scripts.drain(..) {
+                            let script = window_scripts.entry(window).or_default();
+                            script.push_str("try {\n");
+                            script.push_str(&s);
+                            script.push_str("\n} catch (e) {\nconsole.error(e);\n}\n");
+                        }
+                        for (window, script) in window_scripts {
+                            let webview = if window == popup::MAIN_WINDOW {
+                                &webview
+                            } else {
+                                match popup_windows.get(&window) {
+                                    Some(p) => &p.webview,
+                                    None => {
+                                        // Closed
+                                        continue;
+                                    },
+                                }
+                            };
+                            match webview.evaluate_script(&script) {
+                                Ok(_) => { },
+                                Err(e) => {
+                                    log.log_err(loga::WARN, e.context("Error executing ipc response script"));
+                                },
+                            };
+                        }
+                    },
+                    _ => (),
+                }
+            }
+        });
+
+        // Nothing kills the services otherwise, since the runtime is never dropped. The
+        // background thread is still driving the runtime here (if it exited early, the
+        // runtime was dropped and the services killed with it).
+        rt_handle.block_on(stop_services.stop_all());
+        exited.notify_waiters();
+        let err = err.lock().unwrap().take();
+        if let Some(e) = err {
+            return Err(e);
+        } else {
+            return Ok(());
+        }
+    }
+
+    match inner() {
+        Ok(_) => { },
+        Err(e) => fatal(e),
+    }
+}
@@ -0,0 +1,107 @@
+use {
+    crate::{
+        popup::MAIN_WINDOW,
+        UserEvent,
+    },
+    gtk::{
+        gdk::{
+            Display,
+            Monitor,
+            Rectangle,
+        },
+        prelude::{
+            MonitorExt,
+            WidgetExt,
+        },
+        ApplicationWindow,
+    },
+    serde_json::json,
+    tao::event_loop::EventLoopProxy,
+};
+
+fn rect_json(r: Rectangle) -> serde_json::Value {
+    return json!({
+        "x": r.x(),
+        "y": r.y(),
+        "width": r.width(),
+        "height": r.height()
+    });
+}
+
+fn monitor_json(display: &Display, monitor: &Monitor) -> serde_json::Value {
+    let index = (0 .. display.n_monitors()).find(|i| display.monitor(*i).as_ref() == Some(monitor));
+    return json!({
+        "index": index,
+        "manufacturer": monitor.manufacturer().map(|x| x.to_string()),
+        "model": monitor.model().map(|x| x.to_string()),
+        "geometry": rect_json(monitor.geometry()),
+        "workarea": rect_json(monitor.workarea()),
+        "scale": monitor.scale_factor(),
+        "width_mm": monitor.width_mm(),
+        "height_mm": monitor.height_mm(),
+        "refresh_rate": monitor.refresh_rate() as f64 / 1000.,
+        "primary": monitor.is_primary()
+    });
+}
+
+/// All monitors, in index order.
+pub fn list(display: &Display) -> serde_json::Value {
+    let mut out = vec![];
+    for i in 0 .. display.n_monitors() {
+        if let Some(m) = display.monitor(i) {
+            out.push(monitor_json(display, &m));
+        }
+    }
+    return serde_json::Value::Array(out);
+}
+
+/// The monitor the window is on, or null if it isn't shown.
+pub fn current(window: &ApplicationWindow) -> serde_json::Value {
+    let display = window.display();
+    let Some(monitor) = window.window().and_then(|w| display.monitor_at_window(&w)) else {
+        return serde_json::Value::Null;
+    };
+    return monitor_json(&display, &monitor);
+}
+
+fn send_event(event_loop: &EventLoopProxy<UserEvent>, type_: &str, monitor: serde_json::Value) {
+    match event_loop.send_event(UserEvent::Script(MAIN_WINDOW, format!("window._wongus.monitor_event({});", json!({
+        "type": type_,
+        "monitor": monitor
+    })))) {
+        Ok(_) => (),
+        Err(_) => (),
+    };
+}
+
+fn watch_scale(display: &Display, monitor: &Monitor, event_loop: &EventLoopProxy<UserEvent>) {
+    monitor.connect_scale_factor_notify({
+        let display = display.clone();
+        let event_loop = event_loop.clone();
+        move |monitor| {
+            send_event(&event_loop, "scale_changed", monitor_json(&display, monitor));
+        }
+    });
+}
+
+/// Send monitor additions, removals, and scale changes to the page.
+pub fn watch(display: &Display, event_loop: EventLoopProxy<UserEvent>) {
+    for i in 0 .. display.n_monitors() {
+        if let Some(m) = display.monitor(i) {
+            watch_scale(display, &m, &event_loop);
+        }
+    }
+    display.connect_monitor_added({
+        let event_loop = event_loop.clone();
+        move |display, monitor| {
+            watch_scale(display, monitor, &event_loop);
+            send_event(&event_loop, "added", monitor_json(display, monitor));
+        }
+    });
+    display.connect_monitor_removed({
+        let event_loop = event_loop.clone();
+        move |display, monitor| {
+            send_event(&event_loop, "removed", monitor_json(display, monitor));
+        }
+    });
+}
@@ -0,0 +1,318 @@
+use {
+    futures_util::{
+        StreamExt,
+        TryStreamExt,
+    },
+    loga::{
+        ea,
+        ResultContext,
+    },
+    netlink_packet_route::{
+        address::nlas::Nla as AddressNla,
+        link::nlas::{
+            Nla as LinkNla,
+            State,
+        },
+        RTNLGRP_IPV4_IFADDR,
+        RTNLGRP_IPV6_IFADDR,
+        RTNLGRP_LINK,
+    },
+    netlink_sys::{
+        AsyncSocket,
+        SocketAddr,
+    },
+    rtnetlink::Handle,
+    serde::Serialize,
+    std::{
+        collections::{
+            BTreeMap,
+            HashMap,
+        },
+        net::{
+            IpAddr,
+            Ipv4Addr,
+            Ipv6Addr,
+        },
+        time::Duration,
+    },
+    tokio::{
+        select,
+        sync::OnceCell,
+        task::JoinHandle,
+        time::sleep,
+    },
+    zbus::zvariant::OwnedObjectPath,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct NetworkAddress {
+    pub address: IpAddr,
+    pub prefix_len: u8,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct NetworkWifi {
+    pub ssid: String,
+    /// Signal strength, 0-100.
+    pub strength: u8,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct NetworkInterface {
+    pub index: u32,
+    pub name: String,
+    /// Operational state: `up`, `down`, `dormant`, `lower_layer_down`,
+    /// `not_present`, `testing`, or `unknown`.
+    pub state: &'static str,
+    pub mac: Option<String>,
+    pub mtu: Option<u32>,
+    pub addresses: Vec<NetworkAddress>,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    /// Only present for wireless interfaces managed by NetworkManager with an active
+    /// connection.
+    pub wifi: Option<NetworkWifi>,
+}
+
+fn state_str(state: &State) -> &'static str {
+    match state {
+        State::Up => "up",
+        State::Down => "down",
+        State::Dormant => "dormant",
+        State::LowerLayerDown => "lower_layer_down",
+        State::NotPresent => "not_present",
+        State::Testing => "testing",
+        State::Unknown | State::Other(_) => "unknown",
+    }
+}
+
+fn parse_ip(bytes: &[u8]) -> Option<IpAddr> {
+    if let Ok(b) = <[u8; 4]>::try_from(bytes) {
+        return Some(IpAddr::V4(Ipv4Addr::from(b)));
+    }
+    if let Ok(b) = <[u8; 16]>::try_from(bytes) {
+        return Some(IpAddr::V6(Ipv6Addr::from(b)));
+    }
+    return None;
+}
+
+/// Read the nth u64 of a `rtnl_link_stats64` struct.
+fn stat64(bytes: &[u8], i: usize) -> u64 {
+    let Some(b) = bytes.get(i * 8 .. (i + 1) * 8) else {
+        return 0;
+    };
+    return u64::from_ne_bytes(b.try_into().unwrap());
+}
+
+/// Shortest `refresh` for `watch`.
+pub const MIN_REFRESH: Duration = Duration::from_millis(250);
+const NM: &str = "org.freedesktop.NetworkManager";
+
+/// System D-Bus connection shared by all gets and watches.
+static DBUS: OnceCell<zbus::Connection> = OnceCell::const_new();
+
+/// Aborts the rtnetlink connection task (closing its socket) when the get or
+/// watch using it ends.
+struct ConnTask(JoinHandle<()>);
+
+impl Drop for ConnTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// NetworkManager device proxies, kept between snapshots.
+struct Wifi {
+    dbus: zbus::Connection,
+    /// Wireless device proxy by interface name, or `None` if the interface isn't a
+    /// wifi device managed by NetworkManager.
+    devices: HashMap<String, Option<zbus::Proxy<'static>>>,
+}
+
+impl Wifi {
+    async fn new() -> Option<Self> {
+        // Failures aren't cached, so a system bus that comes up later is picked up by new
+        // watches
+        let dbus = DBUS.get_or_try_init(|| zbus::Connection::system()).await.ok()?;
+        return Some(Wifi {
+            dbus: dbus.clone(),
+            devices: HashMap::new(),
+        });
+    }
+
+    async fn lookup_device(&self, ifname: &str) -> Result<Option<zbus::Proxy<'static>>, loga::Error> {
+        let device_path =
+            zbus::Proxy::new(&self.dbus, NM, "/org/freedesktop/NetworkManager", NM)
+                .await
+                .context("Error creating NetworkManager proxy")?
+                .call::<_, _, OwnedObjectPath>("GetDeviceByIpIface", &(ifname,))
+                .await
+                .context("Error looking up NetworkManager device")?;
+        let device =
+            zbus::Proxy::new(&self.dbus, NM, device_path.clone(), "org.freedesktop.NetworkManager.Device")
+                .await
+                .context("Error creating NetworkManager device proxy")?;
+
+        // NM_DEVICE_TYPE_WIFI
+        if device.get_property::<u32>("DeviceType").await.context("Error getting device type")? != 2 {
+            return Ok(None);
+        }
+        return Ok(
+            Some(
+                zbus::Proxy::new(&self.dbus, NM, device_path, "org.freedesktop.NetworkManager.Device.Wireless")
+                    .await
+                    .context("Error creating NetworkManager wireless device proxy")?,
+            ),
+        );
+    }
+
+    async fn get(&mut self, ifname: &str) -> Result<Option<NetworkWifi>, loga::Error> {
+        if !self.devices.contains_key(ifname) {
+            // Errors here are normal (NetworkManager not running, unmanaged device)
+            let device = self.lookup_device(ifname).await.ok().flatten();
+            self.devices.insert(ifname.to_string(), device);
+        }
+        let Some(device) = self.devices.get(ifname).unwrap() else {
+            return Ok(None);
+        };
+        let ap_path =
+            device
+                .get_property::<OwnedObjectPath>("ActiveAccessPoint")
+                .await
+                .context("Error getting active access point")?;
+        if ap_path.as_str() == "/" {
+            return Ok(None);
+        }
+        let ap =
+            zbus::Proxy::new(&self.dbus, NM, ap_path, "org.freedesktop.NetworkManager.AccessPoint")
+                .await
+                .context("Error creating NetworkManager access point proxy")?;
+        return Ok(Some(NetworkWifi {
+            ssid: String::from_utf8_lossy(
+                &ap.get_property::<Vec<u8>>("Ssid").await.context("Error getting access point SSID")?,
+            ).to_string(),
+            strength: ap.get_property::<u8>("Strength").await.context("Error getting access point strength")?,
+        }));
+    }
+}
+
+async fn snapshot(handle: &Handle, wifi: Option<&mut Wifi>) -> Result<Vec<NetworkInterface>, loga::Error> {
+    let mut interfaces = BTreeMap::new();
+    let mut links = Box::pin(handle.link().get().execute());
+    while let Some(link) = links.try_next().await.context("Error listing network links")? {
+        let mut iface = NetworkInterface {
+            index: link.header.index,
+            name: String::new(),
+            state: "unknown",
+            mac: None,
+            mtu: None,
+            addresses: vec![],
+            rx_bytes: 0,
+            tx_bytes: 0,
+            rx_packets: 0,
+            tx_packets: 0,
+            wifi: None,
+        };
+        for nla in link.nlas {
+            match nla {
+                LinkNla::IfName(n) => iface.name = n,
+                LinkNla::OperState(s) => iface.state = state_str(&s),
+                LinkNla::Address(a) => {
+                    iface.mac = Some(a.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":"));
+                },
+                LinkNla::Mtu(m) => iface.mtu = Some(m),
+                LinkNla::Stats64(s) => {
+                    iface.rx_packets = stat64(&s, 0);
+                    iface.tx_packets = stat64(&s, 1);
+                    iface.rx_bytes = stat64(&s, 2);
+                    iface.tx_bytes = stat64(&s, 3);
+                },
+                _ => { },
+            }
+        }
+        interfaces.insert(iface.index, iface);
+    }
+    let mut addresses = Box::pin(handle.address().get().execute());
+    while let Some(addr) = addresses.try_next().await.context("Error listing network addresses")? {
+        let Some(iface) = interfaces.get_mut(&addr.header.index) else {
+            continue;
+        };
+        let mut address = None;
+        for nla in addr.nlas {
+            match nla {
+                // Local is the interface's own address on point-to-point links, where
+                // Address is the peer
+                AddressNla::Local(a) => address = parse_ip(&a),
+                AddressNla::Address(a) if address.is_none() => address = parse_ip(&a),
+                _ => { },
+            }
+        }
+        if let Some(address) = address {
+            iface.addresses.push(NetworkAddress {
+                address: address,
+                prefix_len: addr.header.prefix_len,
+            });
+        }
+    }
+    if let Some(wifi) = wifi {
+        // Forget devices for interfaces that are gone, in case the name is reused
+        wifi.devices.retain(|name, _| interfaces.values().any(|i| &i.name == name));
+        for iface in interfaces.values_mut() {
+            iface.wifi = wifi.get(&iface.name).await.ok().flatten();
+        }
+    }
+    return Ok(interfaces.into_values().collect());
+}
+
+pub async fn get() -> Result<Vec<NetworkInterface>, loga::Error> {
+    let (conn, handle, _) = rtnetlink::new_connection().context("Error opening rtnetlink connection")?;
+    let _conn = ConnTask(tokio::spawn(conn));
+    let mut wifi = Wifi::new().await;
+    return snapshot(&handle, wifi.as_mut()).await;
+}
+
+/// Calls `cb` with the current state of all interfaces and then again whenever a
+/// link or address changes, plus every `refresh` if set (for traffic counters and
+/// signal strength, which don't produce change events). Only returns on error.
+pub async fn watch(
+    refresh: Option<Duration>,
+    mut cb: impl FnMut(Vec<NetworkInterface>),
+) -> Result<(), loga::Error> {
+    let (mut conn, handle, mut messages) =
+        rtnetlink::new_connection().context("Error opening rtnetlink connection")?;
+    let groups = (1 << (RTNLGRP_LINK - 1)) | (1 << (RTNLGRP_IPV4_IFADDR - 1)) | (1 << (RTNLGRP_IPV6_IFADDR - 1));
+    conn
+        .socket_mut()
+        .socket_mut()
+        .bind(&SocketAddr::new(0, groups))
+        .context_with("Error subscribing to rtnetlink events", ea!(groups = groups.to_string()))?;
+    let _conn = ConnTask(tokio::spawn(conn));
+    let mut wifi = Wifi::new().await;
+    loop {
+        cb(snapshot(&handle, wifi.as_mut()).await?);
+        select!{
+            m = messages.next() => {
+                if m.is_none() {
+                    return Err(loga::err("Rtnetlink event stream ended unexpectedly"));
+                }
+
+                // Changes usually come in bursts (ex: link up, then addresses), wait for
+                // things to settle then drain
+                sleep(Duration::from_millis(100)).await;
+                while let Ok(Some(_)) = messages.try_next() { }
+            },
+            _ = async {
+                match refresh {
+                    Some(r) => sleep(r).await,
+                    None => std::future::pending::<()>().await,
+                }
+            } => {
+            }
+        }
+    }
+}
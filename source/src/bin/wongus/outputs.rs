@@ -0,0 +1,133 @@
+use {
+    loga::ResultContext,
+    wayland_client::{
+        globals::{
+            registry_queue_init,
+            GlobalListContents,
+        },
+        protocol::{
+            wl_output::{
+                self,
+                WlOutput,
+            },
+            wl_registry::{
+                self,
+                WlRegistry,
+            },
+        },
+        Connection,
+        Dispatch,
+        QueueHandle,
+    },
+    wayland_protocols::xdg::xdg_output::zv1::client::{
+        zxdg_output_manager_v1::{
+            self,
+            ZxdgOutputManagerV1,
+        },
+        zxdg_output_v1::{
+            self,
+            ZxdgOutputV1,
+        },
+    },
+};
+
+/// Wayland output details gdk doesn't expose.
+#[derive(Default, Debug)]
+pub struct Output {
+    /// Connector name, like `DP-1`.
+    pub name: Option<String>,
+    /// Human readable description, usually including the make, model, and serial.
+    pub description: Option<String>,
+    /// Position in the global logical coordinate space, the same as the gdk monitor
+    /// geometry position.
+    pub x: i32,
+    pub y: i32,
+}
+
+struct State {
+    outputs: Vec<Output>,
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for State {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WlRegistry,
+        _event: wl_registry::Event,
+        _data: &GlobalListContents,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) { }
+}
+
+impl Dispatch<WlOutput, ()> for State {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WlOutput,
+        _event: wl_output::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) { }
+}
+
+impl Dispatch<ZxdgOutputManagerV1, ()> for State {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZxdgOutputManagerV1,
+        _event: zxdg_output_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) { }
+}
+
+impl Dispatch<ZxdgOutputV1, usize> for State {
+    fn event(
+        state: &mut Self,
+        _proxy: &ZxdgOutputV1,
+        event: zxdg_output_v1::Event,
+        index: &usize,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let output = &mut state.outputs[*index];
+        match event {
+            zxdg_output_v1::Event::LogicalPosition { x, y } => {
+                output.x = x;
+                output.y = y;
+            },
+            zxdg_output_v1::Event::Name { name } => {
+                output.name = Some(name);
+            },
+            zxdg_output_v1::Event::Description { description } => {
+                output.description = Some(description);
+            },
+            _ => { },
+        }
+    }
+}
+
+/// Get the current outputs from the compositor, over a separate wayland
+/// connection.
+pub fn query() -> Result<Vec<Output>, loga::Error> {
+    let conn = Connection::connect_to_env().context("Error connecting to wayland compositor")?;
+    let (globals, mut queue) = registry_queue_init::<State>(&conn).context("Error listing wayland globals")?;
+    let qh = queue.handle();
+
+    // Names and descriptions were added in version 2
+    let manager =
+        globals
+            .bind::<ZxdgOutputManagerV1, _, _>(&qh, 2 ..= 3, ())
+            .context("Compositor doesn't support xdg-output v2")?;
+    let mut state = State { outputs: vec![] };
+    for global in globals.contents().clone_list() {
+        if global.interface != "wl_output" {
+            continue;
+        }
+        let output = globals.registry().bind::<WlOutput, _, _>(global.name, global.version.min(4), &qh, ());
+        manager.get_xdg_output(&output, &qh, state.outputs.len());
+        state.outputs.push(Output::default());
+    }
+    queue.roundtrip(&mut state).context("Error getting wayland output details")?;
+    return Ok(state.outputs);
+}
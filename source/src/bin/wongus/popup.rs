@@ -0,0 +1,366 @@
+use {
+    crate::UserEvent,
+    gtk::{
+        gdk,
+        glib,
+        prelude::{
+            ContainerExt,
+            EventControllerExt,
+            GestureSingleExt,
+            GtkWindowExt,
+            MonitorExt,
+            WidgetExt,
+        },
+        ApplicationWindow,
+    },
+    gtk_layer_shell::LayerShell,
+    http::Uri,
+    loga::{
+        ea,
+        ErrContext,
+        ResultContext,
+    },
+    serde::Deserialize,
+    std::{
+        collections::HashMap,
+        sync::{
+            atomic::{
+                AtomicUsize,
+                Ordering,
+            },
+            Arc,
+            Mutex,
+        },
+    },
+    tao::event_loop::EventLoopProxy,
+    tokio::sync::Notify,
+    wry::{
+        WebView,
+        WebViewBuilder,
+        WebViewBuilderExtUnix,
+    },
+};
+
+/// The window id of the main page.
+pub const MAIN_WINDOW: usize = 0;
+
+/// Ipc request and callback ids are allocated by each page from its own range,
+/// starting at `window id << WINDOW_ID_BITS`, so responses and callbacks can be
+/// routed to the page that made the request.
+pub const WINDOW_ID_BITS: u32 = 32;
+
+/// The window (main page or popup) that allocated a request or callback id.
+pub fn window_of(id: usize) -> usize {
+    return id >> WINDOW_ID_BITS;
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub struct IPCRect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub struct IPCSize {
+    pub width: f64,
+    pub height: f64,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum IPCPopupLayer {
+    /// Above normal windows, below fullscreen windows.
+    Top,
+    /// Above everything.
+    #[default]
+    Overlay,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct IPCReqPopupOpen {
+    /// Absolute url to load, from the content root, `wongus-file://`, or the server.
+    /// Mutually exclusive with `html`.
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Html to show. Mutually exclusive with `url`.
+    #[serde(default)]
+    pub html: Option<String>,
+    /// Area in the main window (in css pixels) the popup belongs to, like the
+    /// button that opened it. The popup is placed beside it, on the side away from
+    /// the edge the main window is attached to.
+    pub anchor_rect: IPCRect,
+    pub size: IPCSize,
+    #[serde(default)]
+    pub layer: IPCPopupLayer,
+    /// Allow keyboard focus. The popup also closes when it loses focus (which only
+    /// happens once it's been focused, ex: by clicking in it).
+    #[serde(default)]
+    pub keyboard: bool,
+    /// Close the popup when the pointer leaves it.
+    #[serde(default)]
+    pub close_on_leave: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct IPCReqPopupPost {
+    /// Window id to send to, the popup id from the main window or 0 from a popup.
+    pub to: usize,
+    pub message: serde_json::Value,
+}
+
+/// Which edges the main window is attached to.
+pub struct ParentEdges {
+    pub top: bool,
+    pub right: bool,
+    pub bottom: bool,
+    pub left: bool,
+}
+
+/// Open popups, shared with the ipc thread so requests from a popup can be tied
+/// to its lifetime.
+pub struct Popups {
+    next_id: AtomicUsize,
+    closed: Mutex<HashMap<usize, Arc<Notify>>>,
+}
+
+impl Popups {
+    pub fn new() -> Self {
+        return Popups {
+            next_id: AtomicUsize::new(MAIN_WINDOW + 1),
+            closed: Mutex::new(HashMap::new()),
+        };
+    }
+
+    /// Allocate a window id for a new popup.
+    pub fn add(&self) -> usize {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.closed.lock().unwrap().insert(id, Arc::new(Notify::new()));
+        return id;
+    }
+
+    /// Notified when popup `id` closes, or `None` if it's already closed. Used in
+    /// place of the main page's navigation notification for requests from the
+    /// popup.
+    pub fn closed(&self, id: usize) -> Option<Arc<Notify>> {
+        return self.closed.lock().unwrap().get(&id).cloned();
+    }
+
+    /// Stop anything started by popup `id`'s requests, for when its page navigates.
+    pub fn navigated(&self, id: usize) {
+        if let Some(closed) = self.closed.lock().unwrap().get(&id) {
+            closed.notify_waiters();
+        }
+    }
+
+    /// Forget popup `id`, stopping anything started by its requests.
+    pub fn remove(&self, id: usize) {
+        if let Some(closed) = self.closed.lock().unwrap().remove(&id) {
+            closed.notify_waiters();
+        }
+    }
+}
+
+/// Top left corner of the popup relative to the monitor.
+fn position(edges: &ParentEdges, monitor: (f64, f64), parent: (f64, f64), anchor: IPCRect, size: IPCSize) -> (i32, i32) {
+    fn origin(start: bool, end: bool, monitor: f64, parent: f64) -> f64 {
+        if start {
+            return 0.;
+        } else if end {
+            return monitor - parent;
+        } else {
+            return ((monitor - parent) / 2.).floor();
+        }
+    }
+
+    let x = origin(edges.left, edges.right, monitor.0, parent.0) + anchor.x;
+    let y = origin(edges.top, edges.bottom, monitor.1, parent.1) + anchor.y;
+
+    // Open away from the edge the parent is attached to
+    let (x, y) = if edges.top && !edges.bottom {
+        (x, y + anchor.height)
+    } else if edges.bottom && !edges.top {
+        (x, y - size.height)
+    } else if edges.left && !edges.right {
+        (x + anchor.width, y)
+    } else if edges.right && !edges.left {
+        (x - size.width, y)
+    } else {
+        (x, y + anchor.height)
+    };
+    return (
+        x.min(monitor.0 - size.width).max(0.).round() as i32,
+        y.min(monitor.1 - size.height).max(0.).round() as i32,
+    );
+}
+
+/// Call `f` when a pointer button is pressed anywhere in `window`, before the
+/// webview sees it. The gesture stops working when dropped.
+pub fn on_press(window: &ApplicationWindow, f: impl Fn() + 'static) -> gtk::GestureMultiPress {
+    let gesture = gtk::GestureMultiPress::new(window);
+
+    // Any button
+    gesture.set_button(0);
+    gesture.set_propagation_phase(gtk::PropagationPhase::Capture);
+    gesture.connect_pressed(move |_, _, _, _| f());
+    return gesture;
+}
+
+pub struct PopupWindow {
+    gtk_window: ApplicationWindow,
+    pub webview: WebView,
+    _press: gtk::GestureMultiPress,
+}
+
+impl PopupWindow {
+    pub fn close(self) {
+        drop(self.webview);
+        self.gtk_window.close();
+    }
+}
+
+/// The `scheme://authority` of `url`, lowercased.
+pub fn origin_of(url: &str) -> Result<String, loga::Error> {
+    let uri = url.parse::<Uri>().context_with("Invalid url", ea!(url = url))?;
+    let scheme = uri.scheme_str().context_with("Url must be absolute", ea!(url = url))?;
+    let authority = uri.authority().context_with("Url is missing a host", ea!(url = url))?;
+    return Ok(format!("{}://{}", scheme, authority).to_ascii_lowercase());
+}
+
+/// Create popup `id` next to `anchor_rect` in the main window. `webview` should
+/// be set up like the main page's webview, and `url` must be on one of `origins`
+/// (as from `origin_of`). Main thread.
+pub fn open(
+    app: &gtk::Application,
+    parent: &ApplicationWindow,
+    edges: &ParentEdges,
+    event_loop: EventLoopProxy<UserEvent>,
+    id: usize,
+    req: IPCReqPopupOpen,
+    origins: &[String],
+    mut webview: WebViewBuilder,
+) -> Result<PopupWindow, loga::Error> {
+    match (req.url, req.html) {
+        (Some(url), None) => {
+            if !origins.contains(&origin_of(&url)?) {
+                return Err(
+                    loga::err_with(
+                        "Popups can only load urls from the content root, wongus-file, or the server",
+                        ea!(url = url),
+                    ),
+                );
+            }
+            webview = webview.with_url(url);
+        },
+        (None, Some(html)) => {
+            webview = webview.with_html(html);
+        },
+        _ => {
+            return Err(loga::err("Exactly one of `url` or `html` must be set"));
+        },
+    }
+    if !(req.size.width >= 1. && req.size.height >= 1.) {
+        return Err(loga::err("Popup size must be positive"));
+    }
+    let monitor =
+        parent
+            .window()
+            .and_then(|w| parent.display().monitor_at_window(&w))
+            .context("Main window isn't on a monitor")?;
+    let geom = monitor.geometry();
+    let (x, y) =
+        position(
+            edges,
+            (geom.width() as f64, geom.height() as f64),
+            (parent.allocated_width() as f64, parent.allocated_height() as f64),
+            req.anchor_rect,
+            req.size,
+        );
+    let gtk_window = gtk::ApplicationWindow::new(app);
+    gtk_window.init_layer_shell();
+    gtk_window.set_monitor(&monitor);
+    gtk_window.set_layer(match req.layer {
+        IPCPopupLayer::Top => gtk_layer_shell::Layer::Top,
+        IPCPopupLayer::Overlay => gtk_layer_shell::Layer::Overlay,
+    });
+
+    // Position relative to the monitor edges rather than other surfaces' exclusive
+    // zones (including the main window's)
+    gtk_window.set_exclusive_zone(-1);
+    gtk_window.set_anchor(gtk_layer_shell::Edge::Top, true);
+    gtk_window.set_anchor(gtk_layer_shell::Edge::Left, true);
+    gtk_window.set_layer_shell_margin(gtk_layer_shell::Edge::Top, y);
+    gtk_window.set_layer_shell_margin(gtk_layer_shell::Edge::Left, x);
+    gtk_window.set_keyboard_mode(if req.keyboard {
+        gtk_layer_shell::KeyboardMode::OnDemand
+    } else {
+        gtk_layer_shell::KeyboardMode::None
+    });
+    gtk_window.set_width_request(req.size.width.ceil() as i32);
+    gtk_window.set_height_request(req.size.height.ceil() as i32);
+    if let Some(visual) = GtkWindowExt::screen(&gtk_window).and_then(|s| s.rgba_visual()) {
+        gtk_window.set_visual(Some(&visual));
+    }
+    gtk_window.set_skip_pager_hint(true);
+    gtk_window.set_skip_taskbar_hint(true);
+    gtk_window.set_resizable(false);
+    gtk_window.set_app_paintable(true);
+    gtk_window.set_decorated(false);
+
+    // Popups close when something else is clicked. Wayland doesn't say when other
+    // programs are clicked, so this only covers the main window and other popups
+    // (the main window's side is set up in `main`).
+    let press = on_press(&gtk_window, {
+        let event_loop = event_loop.clone();
+        move || {
+            match event_loop.send_event(UserEvent::PopupCloseOthers(Some(id))) {
+                Ok(_) => (),
+                Err(_) => (),
+            };
+        }
+    });
+    gtk_window.connect_focus_out_event({
+        let event_loop = event_loop.clone();
+        move |_, _| {
+            match event_loop.send_event(UserEvent::PopupClose(id)) {
+                Ok(_) => (),
+                Err(_) => (),
+            };
+            return glib::Propagation::Proceed;
+        }
+    });
+    if req.close_on_leave {
+        gtk_window.add_events(gdk::EventMask::LEAVE_NOTIFY_MASK);
+        gtk_window.connect_leave_notify_event(move |_, ev| {
+            // Moving onto the webview's own window
+            if ev.detail() == gdk::NotifyType::Inferior {
+                return glib::Propagation::Proceed;
+            }
+            match event_loop.send_event(UserEvent::PopupClose(id)) {
+                Ok(_) => (),
+                Err(_) => (),
+            };
+            return glib::Propagation::Proceed;
+        });
+    }
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    gtk_window.add(&vbox);
+    let webview = match webview.build_gtk(&vbox) {
+        Ok(w) => w,
+        Err(e) => {
+            gtk_window.close();
+            return Err(e.context("Error initializing popup webview"));
+        },
+    };
+    gtk_window.show_all();
+    return Ok(PopupWindow {
+        gtk_window: gtk_window,
+        webview: webview,
+        _press: press,
+    });
+}
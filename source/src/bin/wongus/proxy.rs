@@ -0,0 +1,208 @@
+use {
+    http::{
+        header::{
+            ACCEPT,
+            CONNECTION,
+            CONTENT_TYPE,
+            HOST,
+            TRANSFER_ENCODING,
+        },
+        Method,
+        Request,
+        Response,
+        Uri,
+    },
+    http_body_util::{
+        BodyExt,
+        Full,
+    },
+    hyper::body::Bytes,
+    hyper_util::{
+        client::legacy::{
+            connect::HttpConnector,
+            Client,
+        },
+        rt::TokioExecutor,
+    },
+    loga::{
+        ea,
+        ErrContext,
+        ResultContext,
+    },
+    std::{
+        borrow::Cow,
+        sync::Arc,
+        time::Duration,
+    },
+    tokio::time::sleep,
+    wry::RequestAsyncResponder,
+};
+
+/// Shown instead of the page when the server can't be reached. It polls the server
+/// with backoff and reloads once it responds.
+const UNAVAILABLE_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<style>
+body { margin: 0; display: flex; align-items: center; justify-content: center; height: 100vh; font-family: sans-serif; color: white; background: rgba(0, 0, 0, 0.6); }
+p { margin: 0.5em; text-align: center; }
+</style>
+</head>
+<body>
+<div>
+<p>Server unavailable</p>
+<p id="detail"></p>
+</div>
+<script>
+(async () => {
+  let delay = 500;
+  while (true) {
+    document.getElementById("detail").textContent = `Retrying in ${Math.round(delay / 1000)}s`;
+    await new Promise((r) => setTimeout(r, delay));
+    try {
+      const resp = await fetch(location.href, { method: "HEAD", cache: "no-store" });
+      if (!resp.headers.has("x-wongus-unavailable")) {
+        location.reload();
+        return;
+      }
+    } catch (e) { }
+    delay = Math.min(delay * 2, 10000);
+  }
+})();
+</script>
+</body>
+</html>
+"#;
+
+/// Backoff between attempts to connect to the server for a single request, before
+/// giving up and showing the unavailable page. Only requests that are safe to
+/// repeat are retried.
+const RETRY_DELAYS: [Duration; 3] =
+    [Duration::from_millis(100), Duration::from_millis(300), Duration::from_millis(1000)];
+
+pub struct Proxy {
+    /// Scheme and authority of the server.
+    base: String,
+    /// Path and query of the server url, where the page starts.
+    start: String,
+    client: Client<HttpConnector, Full<Bytes>>,
+}
+
+impl Proxy {
+    /// `server` is the http url of the server's page. All requests are forwarded to
+    /// the same server.
+    pub fn new(server: &str) -> Result<Self, loga::Error> {
+        let uri = server.parse::<Uri>().context_with("Invalid server url", ea!(url = server))?;
+        if uri.scheme_str() != Some("http") {
+            return Err(loga::err_with("Only http server urls can be proxied", ea!(url = server)));
+        }
+        let authority = uri.authority().context_with("Server url is missing a host", ea!(url = server))?;
+        return Ok(Proxy {
+            base: format!("http://{}", authority),
+            start: uri.path_and_query().map(|p| p.as_str()).unwrap_or("/").to_string(),
+            client: Client::builder(TokioExecutor::new()).build_http(),
+        });
+    }
+
+    /// The url to load in the webview.
+    pub fn start_url(&self) -> String {
+        return format!("wongus-proxy://x{}", self.start);
+    }
+
+    async fn forward(&self, request: &Request<Vec<u8>>) -> Result<Response<Cow<'static, [u8]>>, loga::Error> {
+        let path_and_query = request.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+        let url = format!("{}{}", self.base, path_and_query);
+        let retry = request.method() == Method::GET || request.method() == Method::HEAD ||
+            request.method() == Method::OPTIONS;
+        let mut delays = RETRY_DELAYS.iter();
+        loop {
+            let mut req = Request::builder().method(request.method()).uri(&url);
+            for (k, v) in request.headers() {
+                // Let the client set the host for the server
+                if k == HOST {
+                    continue;
+                }
+                req = req.header(k, v);
+            }
+            let req =
+                req
+                    .body(Full::new(Bytes::from(request.body().clone())))
+                    .context_with("Error building proxied request", ea!(url = &url))?;
+            match self.client.request(req).await {
+                Ok(resp) => {
+                    let (parts, body) = resp.into_parts();
+                    let body =
+                        body
+                            .collect()
+                            .await
+                            .context_with("Error reading proxied response body", ea!(url = &url))?
+                            .to_bytes();
+                    let mut out = Response::builder().status(parts.status);
+                    for (k, v) in &parts.headers {
+                        // The body is forwarded whole, so drop connection-level headers
+                        if k == TRANSFER_ENCODING || k == CONNECTION {
+                            continue;
+                        }
+                        out = out.header(k, v);
+                    }
+                    return Ok(out.body(Cow::Owned(body.to_vec())).unwrap());
+                },
+                Err(e) => {
+                    // Other errors (ex: timeouts) may have reached the server
+                    let delay = if retry && e.is_connect() {
+                        delays.next()
+                    } else {
+                        None
+                    };
+                    let Some(delay) = delay else {
+                        return Err(e.context_with("Error connecting to server", ea!(url = &url)));
+                    };
+                    sleep(*delay).await;
+                },
+            }
+        }
+    }
+}
+
+/// Forward a custom protocol request to the server. If it can't be reached,
+/// navigations get a page that waits for the server to come back and other
+/// requests get a plain error.
+pub fn proxy(
+    rt: &tokio::runtime::Handle,
+    log: &loga::Log,
+    proxy: &Arc<Proxy>,
+    request: Request<Vec<u8>>,
+    responder: RequestAsyncResponder,
+) {
+    let log = log.clone();
+    let proxy = proxy.clone();
+    rt.spawn(async move {
+        match proxy.forward(&request).await {
+            Ok(r) => {
+                responder.respond(r);
+            },
+            Err(e) => {
+                let navigation =
+                    request
+                        .headers()
+                        .get(ACCEPT)
+                        .and_then(|a| a.to_str().ok())
+                        .is_some_and(|a| a.contains("text/html"));
+                let resp = Response::builder().status(502).header("x-wongus-unavailable", "1");
+                if navigation {
+                    log.log_err(loga::DEBUG, e.context("Server unavailable, showing placeholder page"));
+                    responder.respond(
+                        resp
+                            .header(CONTENT_TYPE, "text/html")
+                            .body(Cow::Borrowed(UNAVAILABLE_HTML.as_bytes()))
+                            .unwrap(),
+                    );
+                } else {
+                    log.log_err(loga::DEBUG, e.context("Server unavailable"));
+                    responder.respond(resp.body(Cow::Borrowed(&[][..])).unwrap());
+                }
+            },
+        }
+    });
+}
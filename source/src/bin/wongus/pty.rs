@@ -0,0 +1,221 @@
+use {
+    crate::{
+        popup::window_of,
+        stream::StreamQueues,
+    },
+    base64::{
+        prelude::BASE64_STANDARD,
+        Engine,
+    },
+    loga::{
+        ea,
+        ResultContext,
+    },
+    portable_pty::{
+        native_pty_system,
+        ChildKiller,
+        CommandBuilder,
+        MasterPty,
+        PtySize,
+    },
+    serde::Deserialize,
+    std::{
+        collections::HashMap,
+        io::{
+            Read,
+            Write,
+        },
+        sync::{
+            atomic::{
+                AtomicUsize,
+                Ordering,
+            },
+            mpsc,
+            Arc,
+            Mutex,
+        },
+    },
+};
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub struct IPCPtySize {
+    pub rows: u16,
+    pub cols: u16,
+}
+
+impl IPCPtySize {
+    fn pty_size(self) -> PtySize {
+        return PtySize {
+            rows: self.rows,
+            cols: self.cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        };
+    }
+}
+
+/// `portable_pty` errors are `anyhow` errors, which don't implement `Error`.
+fn pty_err<E: std::fmt::Display>(message: &'static str) -> impl FnOnce(E) -> loga::Error {
+    return move |e| loga::err(e.to_string()).context(message);
+}
+
+struct Pty {
+    /// Distinguishes this pty from later ones with the same stream id (after a
+    /// reload).
+    serial: usize,
+    master: Box<dyn MasterPty + Send>,
+    /// Input for the writer thread, writes block if the command isn't reading.
+    input: mpsc::Sender<Vec<u8>>,
+    killer: Box<dyn ChildKiller + Send + Sync>,
+}
+
+/// Commands running in pseudo-terminals, by stream callback id.
+pub struct Ptys {
+    log: loga::Log,
+    queues: Arc<StreamQueues>,
+    serial: AtomicUsize,
+    ptys: Mutex<HashMap<usize, Pty>>,
+}
+
+impl Ptys {
+    pub fn new(log: &loga::Log, queues: Arc<StreamQueues>) -> Self {
+        return Ptys {
+            log: log.fork(ea!(sys = "pty")),
+            queues: queues,
+            serial: AtomicUsize::new(0),
+            ptys: Mutex::new(HashMap::new()),
+        };
+    }
+
+    /// Start a command in a new pty, sending its output as base64 chunks to stream
+    /// callback `id`.
+    pub fn spawn(
+        self: &Arc<Self>,
+        id: usize,
+        command: &[String],
+        working_dir: Option<String>,
+        environment: HashMap<String, String>,
+        size: IPCPtySize,
+    ) -> Result<(), loga::Error> {
+        if command.is_empty() {
+            return Err(loga::err("Commandline is empty"));
+        }
+        let pair = native_pty_system().openpty(size.pty_size()).map_err(pty_err("Error opening pty"))?;
+        let mut builder = CommandBuilder::new(&command[0]);
+        builder.args(&command[1..]);
+
+        // Defaults to the home directory otherwise, unlike other commands
+        match working_dir {
+            Some(cwd) => builder.cwd(cwd),
+            None => builder.cwd(std::env::current_dir().context("Error getting current directory")?),
+        }
+        if !environment.contains_key("TERM") {
+            builder.env("TERM", "xterm-256color");
+        }
+        for (k, v) in environment {
+            builder.env(k, v);
+        }
+        let log = self.log.fork(ea!(command = command.join(" ")));
+        let mut child = pair.slave.spawn_command(builder).map_err(pty_err("Error starting command in pty"))?;
+        drop(pair.slave);
+        let mut reader = pair.master.try_clone_reader().map_err(pty_err("Error getting pty reader"))?;
+        let mut writer = pair.master.take_writer().map_err(pty_err("Error getting pty writer"))?;
+        let (input_tx, input_rx) = mpsc::channel::<Vec<u8>>();
+        let serial = self.serial.fetch_add(1, Ordering::Relaxed);
+        if let Some(mut old) = self.ptys.lock().unwrap().insert(id, Pty {
+            serial: serial,
+            master: pair.master,
+            input: input_tx,
+            killer: child.clone_killer(),
+        }) {
+            _ = old.killer.kill();
+        }
+
+        // Pty io is blocking. The writer thread exits when the pty is forgotten.
+        std::thread::spawn({
+            let log = log.clone();
+            move || {
+                for data in input_rx {
+                    if let Err(e) = writer.write_all(&data).and_then(|_| writer.flush()) {
+                        log.log_err(loga::WARN, loga::err(e.to_string()).context("Error writing to pty"));
+                        break;
+                    }
+                }
+            }
+        });
+        std::thread::spawn({
+            let ptys = self.clone();
+            move || {
+                let mut buf = vec![0u8; 64 * 1024];
+                loop {
+                    match reader.read(&mut buf) {
+                        Ok(0) => {
+                            break;
+                        },
+                        Ok(n) => {
+                            ptys
+                                .queues
+                                .send(id, [serde_json::to_string(&BASE64_STANDARD.encode(&buf[..n])).unwrap()]);
+                        },
+                        Err(e) => {
+                            // Linux returns EIO once the child exits and the slave side is closed
+                            if e.raw_os_error() != Some(libc::EIO) {
+                                log.log_err(loga::WARN, loga::err(e.to_string()).context("Error reading from pty"));
+                            }
+                            break;
+                        },
+                    }
+                }
+                match child.wait() {
+                    Ok(status) => {
+                        log.log_with(loga::INFO, "Pty command exited", ea!(status = status.exit_code()));
+                    },
+                    Err(e) => {
+                        log.log_err(loga::WARN, loga::err(e.to_string()).context("Error waiting for pty command"));
+                    },
+                }
+                let mut ptys = ptys.ptys.lock().unwrap();
+                if ptys.get(&id).is_some_and(|p| p.serial == serial) {
+                    ptys.remove(&id);
+                }
+            }
+        });
+        return Ok(());
+    }
+
+    /// Queue input for the pty for stream callback `id`.
+    pub fn write(&self, id: usize, data: Vec<u8>) -> Result<(), loga::Error> {
+        let input = {
+            let ptys = self.ptys.lock().unwrap();
+            ptys.get(&id).context_with("No running pty command for this stream", ea!(id = id))?.input.clone()
+        };
+        input.send(data).map_err(|_| loga::err_with("Pty input is closed", ea!(id = id)))?;
+        return Ok(());
+    }
+
+    pub fn resize(&self, id: usize, size: IPCPtySize) -> Result<(), loga::Error> {
+        let ptys = self.ptys.lock().unwrap();
+        let pty = ptys.get(&id).context_with("No running pty command for this stream", ea!(id = id))?;
+        pty.master.resize(size.pty_size()).map_err(pty_err("Error resizing pty"))?;
+        return Ok(());
+    }
+
+    /// Kill all pty commands, for when the page navigates.
+    pub fn kill_all(&self) {
+        for (_, mut pty) in self.ptys.lock().unwrap().drain() {
+            _ = pty.killer.kill();
+        }
+    }
+
+    /// Kill pty commands started from a window, for when a popup closes.
+    pub fn kill_window(&self, window: usize) {
+        self.ptys.lock().unwrap().retain(|id, pty| {
+            if window_of(*id) != window {
+                return true;
+            }
+            _ = pty.killer.kill();
+            return false;
+        });
+    }
+}
@@ -0,0 +1,224 @@
+use {
+    crate::stream::StreamQueues,
+    loga::{
+        ea,
+        ErrContext,
+        ResultContext,
+    },
+    serde::Deserialize,
+    serde_json::json,
+    std::{
+        os::fd::{
+            AsRawFd,
+            FromRawFd,
+            OwnedFd,
+        },
+        str::FromStr,
+        sync::Arc,
+        time::{
+            SystemTime,
+            UNIX_EPOCH,
+        },
+    },
+    tokio::{
+        io::unix::AsyncFd,
+        process::Command,
+    },
+    wongus::ScheduleWhen,
+};
+
+/// Shortest `interval_ms`, so a typo can't spawn a command every millisecond.
+pub const MIN_INTERVAL_MS: u64 = 100;
+
+/// A wall clock timer. Tokio timers use the monotonic clock, which stops during
+/// suspend and doesn't follow wall clock changes.
+struct WallTimer(AsyncFd<OwnedFd>);
+
+impl WallTimer {
+    fn new() -> Result<Self, loga::Error> {
+        let fd = unsafe {
+            libc::timerfd_create(libc::CLOCK_REALTIME, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC)
+        };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error().context("Error creating timerfd"));
+        }
+        let fd = unsafe {
+            OwnedFd::from_raw_fd(fd)
+        };
+        return Ok(WallTimer(AsyncFd::new(fd).context("Error registering timerfd")?));
+    }
+
+    /// Wait until the wall clock reaches `at`. Returns false if the wall clock was
+    /// changed (ex: manually or by ntp) before then.
+    async fn sleep_until(&self, at: SystemTime) -> Result<bool, loga::Error> {
+        let at = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        if at.is_zero() {
+            // A zero time disarms the timer
+            return Ok(true);
+        }
+        let spec = libc::itimerspec {
+            it_interval: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            it_value: libc::timespec {
+                tv_sec: at.as_secs() as libc::time_t,
+                tv_nsec: at.subsec_nanos() as libc::c_long,
+            },
+        };
+        let res = unsafe {
+            libc::timerfd_settime(
+                self.0.as_raw_fd(),
+                libc::TFD_TIMER_ABSTIME | libc::TFD_TIMER_CANCEL_ON_SET,
+                &spec,
+                std::ptr::null_mut(),
+            )
+        };
+        if res < 0 {
+            return Err(std::io::Error::last_os_error().context("Error setting timerfd"));
+        }
+        loop {
+            let mut guard = self.0.readable().await.context("Error waiting for timerfd")?;
+            let res = guard.try_io(|fd| {
+                let mut expirations = [0u8; 8];
+                let n = unsafe {
+                    libc::read(fd.as_raw_fd(), expirations.as_mut_ptr() as *mut libc::c_void, expirations.len())
+                };
+                if n < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                return Ok(());
+            });
+            match res {
+                Ok(Ok(())) => return Ok(true),
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::ECANCELED) => return Ok(false),
+                Ok(Err(e)) => return Err(e.context("Error reading timerfd")),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct IPCReqSchedule {
+    pub id: usize,
+    /// Cron expression, with seconds (`sec min hour day month weekday [year]`), in
+    /// local time.
+    #[serde(default)]
+    pub cron: Option<String>,
+    /// Run every this many milliseconds (at least `MIN_INTERVAL_MS`), at multiples of
+    /// the interval since the unix epoch in local time.
+    #[serde(default)]
+    pub interval_ms: Option<u64>,
+    /// Run at the start of every minute.
+    #[serde(default)]
+    pub at_wallclock_minute: bool,
+    /// Run this command each time and pass its output to the callback.
+    #[serde(default)]
+    pub command: Option<Vec<String>>,
+}
+
+/// The next scheduled time after now, in local time.
+fn next(when: &ScheduleWhen) -> Option<SystemTime> {
+    return when.next(&chrono::Local::now()).map(SystemTime::from);
+}
+
+async fn run_command(command: &[String]) -> Result<String, loga::Error> {
+    let mut c = Command::new(&command[0]);
+    c.args(&command[1..]);
+    c.kill_on_drop(true);
+    let output = c.output().await.context_with("Error running scheduled command", ea!(command = command.join(" ")))?;
+    if !output.status.success() {
+        return Err(
+            loga::err_with(
+                "Scheduled command exited with unsuccessful status",
+                ea!(
+                    command = command.join(" "),
+                    status = output.status,
+                    stderr = String::from_utf8_lossy(&output.stderr)
+                ),
+            ),
+        );
+    }
+    return Ok(String::from_utf8(output.stdout).context("Scheduled command output isn't valid utf-8")?);
+}
+
+pub struct Schedule {
+    id: usize,
+    when: ScheduleWhen,
+    command: Option<Vec<String>>,
+    timer: WallTimer,
+}
+
+impl Schedule {
+    pub fn new(req: IPCReqSchedule) -> Result<Self, loga::Error> {
+        let when = match (&req.cron, req.interval_ms, req.at_wallclock_minute) {
+            (Some(expr), None, false) => ScheduleWhen::Cron(
+                Box::new(cron::Schedule::from_str(expr).context_with("Invalid cron expression", ea!(cron = expr))?),
+            ),
+            (None, Some(interval_ms), false) => {
+                if interval_ms < MIN_INTERVAL_MS {
+                    return Err(
+                        loga::err_with(
+                            "`interval_ms` is too short",
+                            ea!(interval_ms = interval_ms, min = MIN_INTERVAL_MS),
+                        ),
+                    );
+                }
+                ScheduleWhen::Interval(interval_ms)
+            },
+            (None, None, true) => ScheduleWhen::Interval(60_000),
+            _ => {
+                return Err(loga::err("Exactly one of `cron`, `interval_ms` or `at_wallclock_minute` must be set"));
+            },
+        };
+        if let Some(command) = &req.command {
+            if command.is_empty() {
+                return Err(loga::err("Commandline is empty"));
+            }
+        }
+        return Ok(Schedule {
+            id: req.id,
+            when: when,
+            command: req.command,
+            timer: WallTimer::new()?,
+        });
+    }
+
+    /// Call the stream callback at the scheduled times until the future is dropped.
+    /// If a time is missed (ex: while suspended) the callback is called once as soon
+    /// as possible, rather than once per missed time.
+    pub async fn run(self, queues: Arc<StreamQueues>) -> Result<(), loga::Error> {
+        loop {
+            let Some(mut target) = next(&self.when) else {
+                // Cron schedule with no more times
+                return Ok(());
+            };
+            while !self.timer.sleep_until(target).await? {
+                // Clock moved, the next time might be much earlier now
+                let Some(t) = next(&self.when) else {
+                    return Ok(());
+                };
+                target = target.min(t);
+            }
+            let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+            let value = match &self.command {
+                Some(command) => match run_command(command).await {
+                    Ok(output) => json!({
+                        "time": time,
+                        "output": output
+                    }),
+                    Err(e) => json!({
+                        "time": time,
+                        "error": e.to_string()
+                    }),
+                },
+                None => json!({
+                    "time": time
+                }),
+            };
+            queues.send(self.id, [serde_json::to_string(&value).unwrap()]);
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use {
+    crate::{
+        bundle::Bundle,
+        files,
+    },
+    http::{
+        header::{
+            ACCEPT_RANGES,
+            CONTENT_LENGTH,
+            CONTENT_RANGE,
+            CONTENT_TYPE,
+            ETAG,
+            IF_MODIFIED_SINCE,
+            IF_NONE_MATCH,
+            IF_RANGE,
+            LAST_MODIFIED,
+            RANGE,
+        },
+        HeaderMap,
+        Request,
+        Response,
+    },
+    loga::{
+        ea,
+        DebugDisplay,
+        ErrContext,
+        ResultContext,
+    },
+    std::{
+        borrow::Cow,
+        path::PathBuf,
+        sync::Arc,
+        time::{
+            Duration,
+            SystemTime,
+            UNIX_EPOCH,
+        },
+    },
+    wry::RequestAsyncResponder,
+};
+
+/// Make paths absolute and resolve symlinks so they can be compared with resolved
+/// request paths. Paths that don't exist yet are used as-is.
+pub fn canonicalize_roots(roots: impl IntoIterator<Item = PathBuf>) -> Vec<PathBuf> {
+    return roots.into_iter().map(|r| r.canonicalize().unwrap_or(r)).collect();
+}
+
+fn text_response(status: u16, text: String) -> Response<Cow<'static, [u8]>> {
+    return Response::builder()
+        .header(CONTENT_TYPE, "text/plain")
+        .status(status)
+        .body(Cow::Owned(text.into_bytes()))
+        .unwrap();
+}
+
+enum RangeReq {
+    /// No range or a range we don't support (multiple ranges, non-byte units) -
+    /// serve the whole file.
+    None,
+    Unsatisfiable,
+    /// Inclusive start and end.
+    Range(u64, u64),
+}
+
+fn parse_range(header: &str, len: u64) -> RangeReq {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return RangeReq::None;
+    };
+    if spec.contains(',') {
+        return RangeReq::None;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return RangeReq::None;
+    };
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", "") => return RangeReq::None,
+        ("", suffix) => {
+            // Last N bytes
+            let Ok(suffix) = suffix.parse::<u64>() else {
+                return RangeReq::None;
+            };
+            if suffix == 0 || len == 0 {
+                return RangeReq::Unsatisfiable;
+            }
+            (len.saturating_sub(suffix), len - 1)
+        },
+        (start, end) => {
+            let Ok(start) = start.parse::<u64>() else {
+                return RangeReq::None;
+            };
+            let end = if end.is_empty() {
+                u64::MAX
+            } else {
+                let Ok(end) = end.parse::<u64>() else {
+                    return RangeReq::None;
+                };
+                end
+            };
+            if end < start {
+                return RangeReq::None;
+            }
+            if start >= len {
+                return RangeReq::Unsatisfiable;
+            }
+            (start, end.min(len - 1))
+        },
+    };
+    return RangeReq::Range(start, end);
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, key: http::header::HeaderName) -> Option<&'a str> {
+    return headers.get(key).and_then(|v| v.to_str().ok());
+}
+
+/// Where a protocol handler serves files from.
+pub enum ServeSource {
+    /// The uri path is an absolute filesystem path, which must be within one of
+    /// these (canonicalized) roots.
+    Roots(Vec<PathBuf>),
+    /// The uri path is a path within the bundle.
+    Bundle(Bundle),
+}
+
+enum FoundBody {
+    File(PathBuf),
+    Memory(Arc<Vec<u8>>),
+}
+
+/// A file resolved from a request, ready to serve.
+struct Found {
+    path: PathBuf,
+    len: u64,
+    mtime: Option<SystemTime>,
+    body: FoundBody,
+}
+
+async fn resolve_file(
+    source: &ServeSource,
+    request: &Request<Vec<u8>>,
+) -> Result<Result<Found, Response<Cow<'static, [u8]>>>, loga::Error> {
+    match source {
+        ServeSource::Roots(roots) => {
+            let path = PathBuf::from(request.uri().path());
+            let path = match tokio::fs::canonicalize(&path).await {
+                Ok(p) => p,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Ok(Err(text_response(404, format!("[{}] not found", path.to_string_lossy()))));
+                },
+                Err(e) => {
+                    return Err(e.context_with("Error resolving requested path", ea!(path = path.dbg_str())));
+                },
+            };
+            if !roots.iter().any(|r| path.starts_with(r)) {
+                return Ok(
+                    Err(text_response(403, format!("Access to [{}] is not allowed", path.to_string_lossy()))),
+                );
+            }
+            let meta =
+                tokio::fs::metadata(&path)
+                    .await
+                    .context_with("Error reading requested file metadata", ea!(path = path.dbg_str()))?;
+            if !meta.is_file() {
+                return Ok(Err(text_response(404, format!("[{}] is not a file", path.to_string_lossy()))));
+            }
+            return Ok(Ok(Found {
+                len: meta.len(),
+                mtime: meta.modified().ok(),
+                body: FoundBody::File(path.clone()),
+                path: path,
+            }));
+        },
+        ServeSource::Bundle(bundle) => {
+            let path = request.uri().path();
+            let Some(data) = bundle.get(path) else {
+                return Ok(Err(text_response(404, format!("[{}] not found in bundle", path))));
+            };
+            return Ok(Ok(Found {
+                path: PathBuf::from(path),
+                len: data.len() as u64,
+                mtime: bundle.mtime,
+                body: FoundBody::Memory(data),
+            }));
+        },
+    }
+}
+
+async fn serve_file_inner(
+    source: &ServeSource,
+    request: &Request<Vec<u8>>,
+) -> Result<Response<Cow<'static, [u8]>>, loga::Error> {
+    let found = match resolve_file(source, request).await? {
+        Ok(f) => f,
+        Err(r) => return Ok(r),
+    };
+    let path = &found.path;
+    let len = found.len;
+    let mtime = found.mtime;
+    let mtime_unix = mtime.and_then(|t| t.duration_since(UNIX_EPOCH).ok()).unwrap_or_default();
+    let etag = format!("\"{:x}-{:x}\"", mtime_unix.as_nanos(), len);
+    let mut resp =
+        Response::builder()
+            .header(CONTENT_TYPE, mime_guess::from_path(path).first_or_text_plain().essence_str())
+            .header(ACCEPT_RANGES, "bytes")
+            .header(ETAG, &etag);
+    if let Some(mtime) = mtime {
+        resp = resp.header(LAST_MODIFIED, httpdate::fmt_http_date(mtime));
+    }
+
+    // Conditional requests
+    let headers = request.headers();
+    let not_modified = if let Some(if_none_match) = header_str(headers, IF_NONE_MATCH) {
+        if_none_match
+            .split(',')
+            .map(|t| t.trim())
+            .any(|t| t == "*" || t == etag || t.strip_prefix("W/") == Some(etag.as_str()))
+    } else if let Some(if_modified_since) = header_str(headers, IF_MODIFIED_SINCE) {
+        // Http dates have second precision
+        match httpdate::parse_http_date(if_modified_since) {
+            Ok(since) => UNIX_EPOCH + Duration::from_secs(mtime_unix.as_secs()) <= since,
+            Err(_) => false,
+        }
+    } else {
+        false
+    };
+    if not_modified {
+        return Ok(resp.status(304).body(Cow::Borrowed(&[][..])).unwrap());
+    }
+
+    // Ranges, ignored if `If-Range` doesn't match the current version
+    let range = match header_str(headers, RANGE) {
+        Some(range) => {
+            let if_range_matches = match header_str(headers, IF_RANGE) {
+                Some(if_range) => {
+                    if_range.trim() == etag || mtime.is_some_and(|m| httpdate::fmt_http_date(m) == if_range.trim())
+                },
+                None => true,
+            };
+            if if_range_matches {
+                parse_range(range, len)
+            } else {
+                RangeReq::None
+            }
+        },
+        None => RangeReq::None,
+    };
+    match range {
+        RangeReq::None => {
+            let body = match &found.body {
+                FoundBody::File(path) => tokio::fs::read(path)
+                    .await
+                    .context_with("Error reading requested file", ea!(path = path.dbg_str()))?,
+                FoundBody::Memory(data) => data.as_ref().clone(),
+            };
+            return Ok(resp.header(CONTENT_LENGTH, body.len()).body(Cow::Owned(body)).unwrap());
+        },
+        RangeReq::Unsatisfiable => {
+            return Ok(
+                resp
+                    .status(416)
+                    .header(CONTENT_RANGE, format!("bytes */{}", len))
+                    .body(Cow::Borrowed(&[][..]))
+                    .unwrap(),
+            );
+        },
+        RangeReq::Range(start, end) => {
+            let body = match &found.body {
+                FoundBody::File(path) => files::read_range(path, start, Some(end - start + 1)).await?,
+                FoundBody::Memory(data) => data[start as usize ..= end as usize].to_vec(),
+            };
+            return Ok(
+                resp
+                    .status(206)
+                    .header(
+                        CONTENT_RANGE,
+                        format!("bytes {}-{}/{}", start, (start + body.len() as u64).saturating_sub(1), len),
+                    )
+                    .header(CONTENT_LENGTH, body.len())
+                    .body(Cow::Owned(body))
+                    .unwrap(),
+            );
+        },
+    }
+}
+
+/// Serve a file from `source` for a custom protocol request. The file is read on
+/// the tokio runtime so large files don't block the UI.
+pub fn serve_file(
+    rt: &tokio::runtime::Handle,
+    log: &loga::Log,
+    source: &Arc<ServeSource>,
+    request: Request<Vec<u8>>,
+    responder: RequestAsyncResponder,
+) {
+    let log = log.clone();
+    let source = source.clone();
+    rt.spawn(async move {
+        match serve_file_inner(&source, &request).await {
+            Ok(r) => {
+                if let ServeSource::Roots(roots) = source.as_ref() {
+                    if r.status() == 403 {
+                        log.log_with(
+                            loga::WARN,
+                            "Request for file outside of served directories, rejecting",
+                            ea!(path = request.uri().path(), roots = roots.dbg_str()),
+                        );
+                    }
+                }
+                responder.respond(r);
+            },
+            Err(e) => {
+                let e = e.context("Error making request");
+                log.log_err(loga::WARN, e.clone());
+                responder.respond(text_response(500, e.to_string()));
+            },
+        }
+    });
+}
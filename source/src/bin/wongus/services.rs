@@ -0,0 +1,261 @@
+use {
+    crate::{
+        popup::window_of,
+        stream::StreamQueues,
+    },
+    loga::{
+        ea,
+        DebugDisplay,
+        ErrContext,
+        ResultContext,
+    },
+    serde_json::json,
+    std::{
+        collections::BTreeMap,
+        sync::{
+            Arc,
+            Mutex,
+        },
+        time::{
+            Duration,
+            Instant,
+        },
+    },
+    tokio::{
+        io::{
+            AsyncBufReadExt,
+            AsyncRead,
+            BufReader,
+        },
+        process::Command,
+        select,
+        sync::watch,
+        task::JoinHandle,
+        time::sleep,
+    },
+    wongus::{
+        ServiceConfig,
+        ServiceRestart,
+    },
+};
+
+struct Service {
+    config: ServiceConfig,
+    restart_delay: Duration,
+    restart_max_delay: Duration,
+}
+
+fn config_secs(name: &str, field: &str, secs: f64) -> Result<Duration, loga::Error> {
+    if !(secs > 0.) {
+        return Err(loga::err_with("Service delay must be positive", ea!(service = name, field = field)));
+    }
+    return Ok(
+        Duration::try_from_secs_f64(
+            secs,
+        ).context_with("Service delay is out of range", ea!(service = name, field = field))?,
+    );
+}
+
+/// Long-running commands from the config, supervised independently of the page.
+pub struct Services {
+    log: loga::Log,
+    queues: Arc<StreamQueues>,
+    services: BTreeMap<String, Service>,
+    /// Stream callback ids subscribed to each service's output.
+    subscribers: Mutex<BTreeMap<String, Vec<usize>>>,
+    /// Set to stop supervising and kill the services.
+    stop: watch::Sender<bool>,
+    supervisors: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Services {
+    pub fn new(
+        log: &loga::Log,
+        queues: Arc<StreamQueues>,
+        configs: BTreeMap<String, ServiceConfig>,
+    ) -> Result<Self, loga::Error> {
+        let mut services = BTreeMap::new();
+        for (name, config) in configs {
+            if config.command.is_empty() {
+                return Err(loga::err_with("Service commandline is empty", ea!(service = name)));
+            }
+            let restart_delay =
+                config_secs(&name, "restart_delay_secs", config.restart_delay_secs.unwrap_or(1.))?;
+            let restart_max_delay =
+                config_secs(&name, "restart_max_delay_secs", config.restart_max_delay_secs.unwrap_or(60.))?;
+            services.insert(name, Service {
+                config: config,
+                restart_delay: restart_delay,
+                restart_max_delay: restart_max_delay,
+            });
+        }
+        return Ok(Services {
+            log: log.fork(ea!(sys = "services")),
+            queues: queues,
+            services: services,
+            subscribers: Mutex::new(BTreeMap::new()),
+            stop: watch::Sender::new(false),
+            supervisors: Mutex::new(vec![]),
+        });
+    }
+
+    /// Start supervising all services. They run until `stop_all`.
+    pub fn start(self: &Arc<Self>, rt: &tokio::runtime::Handle) {
+        let mut supervisors = self.supervisors.lock().unwrap();
+        for name in self.services.keys() {
+            supervisors.push(rt.spawn(self.clone().supervise(name.clone())));
+        }
+    }
+
+    /// Kill all services and wait for them to exit, for when wongus exits.
+    pub async fn stop_all(&self) {
+        self.stop.send_replace(true);
+        let supervisors = self.supervisors.lock().unwrap().drain(..).collect::<Vec<_>>();
+        for supervisor in supervisors {
+            _ = supervisor.await;
+        }
+    }
+
+    /// Send output lines from service `name` to the stream callback `id`.
+    pub fn subscribe(&self, name: &str, id: usize) -> Result<(), loga::Error> {
+        if !self.services.contains_key(name) {
+            return Err(loga::err_with("No service with this name", ea!(service = name)));
+        }
+        self.subscribers.lock().unwrap().entry(name.to_string()).or_default().push(id);
+        return Ok(());
+    }
+
+    /// Drop subscriptions, for when the page navigates and the callbacks are gone.
+    pub fn clear_subscribers(&self) {
+        self.subscribers.lock().unwrap().clear();
+    }
+
+    /// Drop subscriptions from a window, for when a popup navigates or closes.
+    pub fn clear_window(&self, window: usize) {
+        for ids in self.subscribers.lock().unwrap().values_mut() {
+            ids.retain(|id| window_of(*id) != window);
+        }
+    }
+
+    fn broadcast(&self, name: &str, stream: &str, line: &str) {
+        let subscribers = self.subscribers.lock().unwrap();
+        let Some(ids) = subscribers.get(name) else {
+            return;
+        };
+        let value = serde_json::to_string(&json!({
+            "stream": stream,
+            "line": line
+        })).unwrap();
+        for id in ids {
+            self.queues.send(*id, [value.clone()]);
+        }
+    }
+
+    async fn forward_output(&self, name: &str, stream: &str, log: &loga::Log, out: impl AsyncRead + Unpin) {
+        let mut lines = BufReader::new(out).lines();
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) => {
+                    log.log_with(loga::INFO, &line, ea!(stream = stream));
+                    self.broadcast(name, stream, &line);
+                },
+                Ok(None) => {
+                    break;
+                },
+                Err(e) => {
+                    log.log_err(loga::WARN, e.context_with("Error reading service output", ea!(stream = stream)));
+                    break;
+                },
+            }
+        }
+    }
+
+    async fn supervise(self: Arc<Self>, name: String) {
+        let service = self.services.get(&name).unwrap();
+        let config = &service.config;
+        let log = self.log.fork(ea!(service = &name));
+        let initial_delay = service.restart_delay;
+        let max_delay = service.restart_max_delay;
+        let mut delay = initial_delay;
+        let mut stop = self.stop.subscribe();
+        loop {
+            if *stop.borrow() {
+                return;
+            }
+            let mut command = Command::new(&config.command[0]);
+            command.args(&config.command[1..]);
+            command.stdin(std::process::Stdio::null());
+            command.stdout(std::process::Stdio::piped());
+            command.stderr(std::process::Stdio::piped());
+            command.kill_on_drop(true);
+            if let Some(cwd) = &config.working_dir {
+                command.current_dir(cwd);
+            }
+            for (k, v) in &config.environment {
+                command.env(k, v);
+            }
+            let started = Instant::now();
+            let spawned = command.spawn().context_with("Error starting service", ea!(command = command.dbg_str()));
+            let success = match spawned {
+                Ok(mut proc) => {
+                    log.log(loga::INFO, "Service started");
+                    let stdout = proc.stdout.take().unwrap();
+                    let stderr = proc.stderr.take().unwrap();
+                    let exited = select!{
+                        status = async {
+                            tokio::join!(
+                                self.forward_output(&name, "stdout", &log, stdout),
+                                self.forward_output(&name, "stderr", &log, stderr)
+                            );
+                            proc.wait().await
+                        } => Some(status),
+                        _ = stop.wait_for(|s| *s) => None,
+                    };
+                    let Some(exited) = exited else {
+                        // Kills and waits
+                        match proc.kill().await {
+                            Ok(_) => {
+                                log.log(loga::INFO, "Service stopped");
+                            },
+                            Err(e) => {
+                                log.log_err(loga::WARN, e.context("Error killing service"));
+                            },
+                        }
+                        return;
+                    };
+                    match exited {
+                        Ok(status) => {
+                            log.log_with(loga::INFO, "Service exited", ea!(status = status));
+                            status.success()
+                        },
+                        Err(e) => {
+                            log.log_err(loga::WARN, e.context("Error waiting for service to exit"));
+                            false
+                        },
+                    }
+                },
+                Err(e) => {
+                    log.log_err(loga::WARN, e);
+                    false
+                },
+            };
+            if success && config.restart == ServiceRestart::OnFailure {
+                return;
+            }
+
+            // Reset backoff if the service ran for a while before exiting
+            if started.elapsed() > max_delay {
+                delay = initial_delay;
+            }
+            log.log_with(loga::INFO, "Restarting service after delay", ea!(delay = delay.dbg_str()));
+            select!{
+                _ = sleep(delay) => {
+                },
+                _ = stop.wait_for(|s| *s) => {
+                    return;
+                },
+            }
+            delay = delay.saturating_mul(2).min(max_delay);
+        }
+    }
+}
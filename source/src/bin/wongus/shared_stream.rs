@@ -0,0 +1,234 @@
+use {
+    crate::{
+        stream::{
+            send_stream_error,
+            StreamItem,
+            StreamMode,
+            StreamQueues,
+            StreamReader,
+        },
+    },
+    loga::{
+        ea,
+        DebugDisplay,
+        ErrContext,
+        ResultContext,
+    },
+    std::{
+        collections::{
+            BTreeMap,
+            HashMap,
+            VecDeque,
+        },
+        sync::{
+            Arc,
+            Mutex,
+        },
+        time::Duration,
+    },
+    tokio::{
+        process::Command,
+        sync::{
+            oneshot,
+            Notify,
+        },
+        task::AbortHandle,
+        time::sleep,
+    },
+};
+
+/// How long to keep a process running after its last subscriber leaves, so a
+/// reloaded page can pick it back up.
+const LINGER: Duration = Duration::from_secs(5);
+
+/// Most recent values kept for `replay`, regardless of what subscribers ask for,
+/// so late subscribers get the same values no matter who started the process.
+pub const REPLAY_MAX: usize = 100;
+
+/// Identifies a shared stream - subscriptions with identical keys share a
+/// process.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct SharedStreamKey {
+    pub command: Vec<String>,
+    pub working_dir: Option<String>,
+    pub environment: BTreeMap<String, String>,
+    pub mode: StreamMode,
+}
+
+struct SharedStreamState {
+    /// Stream callback ids, and error callback ids.
+    subscribers: Vec<(usize, Option<usize>)>,
+    /// Most recent values (json), for late subscribers.
+    replay: VecDeque<String>,
+}
+
+struct SharedStream {
+    state: Mutex<SharedStreamState>,
+    abort: Mutex<Option<AbortHandle>>,
+}
+
+impl SharedStream {
+    fn kill(&self) {
+        if let Some(abort) = self.abort.lock().unwrap().take() {
+            abort.abort();
+        }
+    }
+}
+
+/// Stream command processes shared between subscribers, reference counted by
+/// subscriber.
+pub struct SharedStreams {
+    log: loga::Log,
+    queues: Arc<StreamQueues>,
+    streams: Mutex<HashMap<SharedStreamKey, Arc<SharedStream>>>,
+}
+
+impl SharedStreams {
+    pub fn new(log: &loga::Log, queues: Arc<StreamQueues>) -> Self {
+        return SharedStreams {
+            log: log.fork(ea!(sys = "shared_stream")),
+            queues: queues,
+            streams: Mutex::new(HashMap::new()),
+        };
+    }
+
+    fn start(self: &Arc<Self>, key: &SharedStreamKey) -> Result<Arc<SharedStream>, loga::Error> {
+        if key.command.is_empty() {
+            return Err(loga::err("Commandline is empty"));
+        }
+        let mut command = Command::new(&key.command[0]);
+        command.args(&key.command[1..]);
+        command.stdout(std::process::Stdio::piped());
+        command.kill_on_drop(true);
+        if let Some(cwd) = &key.working_dir {
+            command.current_dir(cwd);
+        }
+        for (k, v) in &key.environment {
+            command.env(k, v);
+        }
+        let log = self.log.fork(ea!(command = command.dbg_str()));
+        let mut proc = command.spawn().context_with("Error starting command", ea!(command = command.dbg_str()))?;
+        let stream = Arc::new(SharedStream {
+            state: Mutex::new(SharedStreamState {
+                subscribers: vec![],
+                replay: VecDeque::new(),
+            }),
+            abort: Mutex::new(None),
+        });
+        let task = tokio::spawn({
+            let streams = self.clone();
+            let stream = stream.clone();
+            let key = key.clone();
+            async move {
+                let mut reader = StreamReader::new(proc.stdout.take().unwrap(), key.mode);
+                loop {
+                    match reader.next().await {
+                        Ok(Some(StreamItem::Value(value))) => {
+                            let mut state = stream.state.lock().unwrap();
+                            for (id, _) in &state.subscribers {
+                                streams.queues.send(*id, [value.clone()]);
+                            }
+                            if state.replay.len() >= REPLAY_MAX {
+                                state.replay.pop_front();
+                            }
+                            state.replay.push_back(value);
+                        },
+                        Ok(Some(StreamItem::Error(e))) => {
+                            let state = stream.state.lock().unwrap();
+                            for (id, error_id) in &state.subscribers {
+                                send_stream_error(&streams.queues, *id, *error_id, &e);
+                            }
+                        },
+                        Ok(None) => {
+                            break;
+                        },
+                        Err(e) => {
+                            log.log_err(loga::WARN, e.context("Error reading output"));
+                            break;
+                        },
+                    }
+                }
+                match proc.wait().await {
+                    Ok(status) => log.log_with(loga::INFO, "Shared streaming command exited", ea!(status = status)),
+                    Err(e) => log.log_err(loga::WARN, e.context("Error waiting for shared streaming command")),
+                }
+
+                // Forget the stream so the next subscriber starts a new process
+                let mut streams_map = streams.streams.lock().unwrap();
+                if streams_map.get(&key).is_some_and(|s| Arc::ptr_eq(s, &stream)) {
+                    streams_map.remove(&key);
+                }
+            }
+        });
+        *stream.abort.lock().unwrap() = Some(task.abort_handle());
+        return Ok(stream);
+    }
+
+    /// Send values from the process for `key` to stream callback `id` (and parse
+    /// errors to `error_id`), starting the process if it isn't already running. Up
+    /// to `replay` (at most `REPLAY_MAX`) of the most recent values are sent
+    /// immediately. The subscription ends when the page navigates.
+    pub async fn subscribe(
+        self: &Arc<Self>,
+        key: SharedStreamKey,
+        id: usize,
+        error_id: Option<usize>,
+        replay: usize,
+        navigated: Arc<Notify>,
+    ) -> Result<(), loga::Error> {
+        // Start listening for navigation before subscribing so a navigation right after
+        // this returns isn't missed
+        let (listening_tx, listening_rx) = oneshot::channel();
+        let (stream_tx, stream_rx) = oneshot::channel::<Arc<SharedStream>>();
+        tokio::spawn({
+            let streams = self.clone();
+            let key = key.clone();
+            async move {
+                let notified = navigated.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+                _ = listening_tx.send(());
+                let Ok(stream) = stream_rx.await else {
+                    // Subscribing failed
+                    return;
+                };
+                notified.await;
+                {
+                    let mut state = stream.state.lock().unwrap();
+                    state.subscribers.retain(|(i, _)| *i != id);
+                    if !state.subscribers.is_empty() {
+                        return;
+                    }
+                }
+                sleep(LINGER).await;
+                let mut streams_map = streams.streams.lock().unwrap();
+                if !stream.state.lock().unwrap().subscribers.is_empty() {
+                    return;
+                }
+                if streams_map.get(&key).is_some_and(|s| Arc::ptr_eq(s, &stream)) {
+                    streams_map.remove(&key);
+                }
+                stream.kill();
+            }
+        });
+        listening_rx.await.context("Navigation listener exited early")?;
+        let stream = {
+            let mut streams = self.streams.lock().unwrap();
+            let stream = match streams.get(&key) {
+                Some(s) => s.clone(),
+                None => {
+                    let s = self.start(&key)?;
+                    streams.insert(key.clone(), s.clone());
+                    s
+                },
+            };
+            let mut state = stream.state.lock().unwrap();
+            self.queues.send(id, state.replay.iter().skip(state.replay.len().saturating_sub(replay)).cloned());
+            state.subscribers.push((id, error_id));
+            drop(state);
+            stream
+        };
+        _ = stream_tx.send(stream);
+        return Ok(());
+    }
+}
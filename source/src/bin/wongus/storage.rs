@@ -0,0 +1,116 @@
+use {
+    crate::files::write_atomic,
+    loga::{
+        ea,
+        DebugDisplay,
+        ErrContext,
+        ResultContext,
+    },
+    sha2::{
+        Digest,
+        Sha256,
+    },
+    std::{
+        collections::BTreeMap,
+        env,
+        path::{
+            Path,
+            PathBuf,
+        },
+    },
+    tokio::sync::Mutex,
+    wongus::Config,
+};
+
+/// Persistent key-value storage for the page, as a json file in the state dir.
+pub struct Storage {
+    /// `None` if there's no state dir, in which case storage requests fail.
+    path: Option<PathBuf>,
+    data: Mutex<Option<BTreeMap<String, serde_json::Value>>>,
+}
+
+impl Storage {
+    /// The storage is stored in a directory named after the window title if set (and
+    /// not empty), otherwise a hash of the content root.
+    pub fn new(config: &Config, content_root: &Path) -> Self {
+        let state_dir = match env::var_os("XDG_STATE_HOME") {
+            Some(d) if !d.is_empty() => Some(PathBuf::from(d)),
+            _ => env::var_os("HOME").filter(|d| !d.is_empty()).map(|d| PathBuf::from(d).join(".local/state")),
+        };
+        let name = match config.title.as_ref().filter(|t| !t.is_empty()) {
+            // No `.` so the name can't be `.` or `..`. The hash keeps titles that sanitize
+            // the same apart.
+            Some(title) => format!(
+                "{}-{}",
+                title.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }).collect::<String>(),
+                &format!("{:x}", Sha256::digest(title.as_bytes()))[.. 8]
+            ),
+            None => format!("{:x}", Sha256::digest(content_root.as_os_str().as_encoded_bytes())),
+        };
+        return Storage {
+            path: state_dir.map(|d| d.join("wongus").join(name).join("storage.json")),
+            data: Mutex::new(None),
+        };
+    }
+
+    async fn with_data<T>(
+        &self,
+        modify: bool,
+        f: impl FnOnce(&mut BTreeMap<String, serde_json::Value>) -> T,
+    ) -> Result<T, loga::Error> {
+        let path = self.path.as_ref().context("Neither XDG_STATE_HOME nor HOME are set, can't locate storage dir")?;
+        let mut data = self.data.lock().await;
+        if data.is_none() {
+            *data = Some(match tokio::fs::read(path).await {
+                Ok(d) => serde_json::from_slice(
+                    &d,
+                ).context_with("Error parsing storage file as json", ea!(path = path.dbg_str()))?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+                Err(e) => {
+                    return Err(e.context_with("Error reading storage file", ea!(path = path.dbg_str())));
+                },
+            });
+        }
+        if !modify {
+            return Ok(f(data.as_mut().unwrap()));
+        }
+
+        // Modify a copy and only keep it once it's written, so memory matches disk if
+        // writing fails
+        let mut new_data = data.as_ref().unwrap().clone();
+        let out = f(&mut new_data);
+
+        // Written atomically so the file is never left half-written
+        let parent = path.parent().unwrap();
+        tokio::fs::create_dir_all(parent)
+            .await
+            .context_with("Error creating storage directory", ea!(path = parent.dbg_str()))?;
+        write_atomic(path, &serde_json::to_vec_pretty(&new_data).unwrap())
+            .await
+            .context("Error writing storage file")?;
+        *data = Some(new_data);
+        return Ok(out);
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<serde_json::Value>, loga::Error> {
+        return self.with_data(false, |d| d.get(key).cloned()).await;
+    }
+
+    pub async fn set(&self, key: String, value: serde_json::Value) -> Result<(), loga::Error> {
+        self.with_data(true, |d| d.insert(key, value)).await?;
+        return Ok(());
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), loga::Error> {
+        self.with_data(true, |d| d.remove(key)).await?;
+        return Ok(());
+    }
+
+    pub async fn list(&self) -> Result<Vec<String>, loga::Error> {
+        return self.with_data(false, |d| d.keys().cloned().collect()).await;
+    }
+}
@@ -0,0 +1,362 @@
+use {
+    crate::{
+        popup::window_of,
+        UserEvent,
+    },
+    base64::{
+        prelude::BASE64_STANDARD,
+        Engine,
+    },
+    loga::{
+        ea,
+        ResultContext,
+    },
+    serde::Deserialize,
+    std::{
+        collections::{
+            HashMap,
+            VecDeque,
+        },
+        sync::{
+            atomic::{
+                AtomicBool,
+                Ordering,
+            },
+            Mutex,
+        },
+        time::Duration,
+    },
+    tao::event_loop::EventLoopProxy,
+    tokio::{
+        io::{
+            AsyncBufReadExt,
+            AsyncRead,
+            AsyncReadExt,
+            BufReader,
+        },
+        select,
+        time::{
+            sleep_until,
+            Instant,
+        },
+    },
+};
+
+/// How command output is split into values for the callback.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamMode {
+    /// Each line as a string.
+    #[default]
+    Lines,
+    /// Each non-empty line parsed as json.
+    JsonLines,
+    /// Strings separated by null bytes (ex: `find -print0`).
+    NullDelimited,
+    /// Data as it's received, base64 encoded (decoded into an `ArrayBuffer` in js).
+    RawChunks,
+}
+
+pub enum StreamItem {
+    /// Json to pass to the callback.
+    Value(String),
+    /// Description of output that couldn't be parsed.
+    Error(String),
+}
+
+/// Splits and parses command output per the mode.
+pub struct StreamReader<R> {
+    reader: BufReader<R>,
+    mode: StreamMode,
+    /// Partial item, kept between calls so `next` is cancel safe.
+    buf: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> StreamReader<R> {
+    pub fn new(reader: R, mode: StreamMode) -> Self {
+        return StreamReader {
+            reader: BufReader::new(reader),
+            mode: mode,
+            buf: vec![],
+        };
+    }
+
+    /// Read the next item, or `None` at the end of the output. Cancel safe.
+    pub async fn next(&mut self) -> Result<Option<StreamItem>, std::io::Error> {
+        loop {
+            let delim = match self.mode {
+                StreamMode::RawChunks => {
+                    let mut chunk = vec![0u8; 64 * 1024];
+                    let n = self.reader.read(&mut chunk).await?;
+                    if n == 0 {
+                        return Ok(None);
+                    }
+                    return Ok(
+                        Some(StreamItem::Value(serde_json::to_string(&BASE64_STANDARD.encode(&chunk[..n])).unwrap())),
+                    );
+                },
+                StreamMode::NullDelimited => 0u8,
+                StreamMode::Lines | StreamMode::JsonLines => b'\n',
+            };
+            let n = self.reader.read_until(delim, &mut self.buf).await?;
+            if n == 0 && self.buf.is_empty() {
+                return Ok(None);
+            }
+            let mut item = std::mem::take(&mut self.buf);
+            if item.last() == Some(&delim) {
+                item.pop();
+            }
+            if delim == b'\n' && item.last() == Some(&b'\r') {
+                item.pop();
+            }
+            let text = match String::from_utf8(item) {
+                Ok(t) => t,
+                Err(e) => {
+                    return Ok(Some(StreamItem::Error(format!("Output isn't valid utf-8: {}", e))));
+                },
+            };
+            if self.mode == StreamMode::JsonLines {
+                if text.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<serde_json::Value>(&text) {
+                    Ok(v) => return Ok(Some(StreamItem::Value(serde_json::to_string(&v).unwrap()))),
+                    Err(e) => {
+                        return Ok(Some(StreamItem::Error(format!("Error parsing line as json: {}\nLine: {}", e, text))));
+                    },
+                }
+            }
+            return Ok(Some(StreamItem::Value(serde_json::to_string(&text).unwrap())));
+        }
+    }
+}
+
+/// Script calling stream callback `id` with each of the json `values`, in order.
+pub fn stream_cb_script(id: usize, values: &[String]) -> String {
+    if values.len() == 1 {
+        return format!("(window._wongus.stream_cbs.get({}))({});", id, values[0]);
+    } else {
+        return format!(
+            "{{ const cb = window._wongus.stream_cbs.get({}); for (const v of [{}]) cb(v); }}",
+            id,
+            values.join(",")
+        );
+    }
+}
+
+/// What to do when a stream produces values faster than the page handles them.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Discard the oldest queued values to make room.
+    #[default]
+    DropOldest,
+    /// Discard new values until there's room.
+    DropNewest,
+    /// Only keep the most recent value (ignores the queue limit).
+    Latest,
+    /// Never discard values (ignores the queue limit). Not selectable by the page,
+    /// used for pty output where dropping chunks would corrupt the terminal stream.
+    #[serde(skip)]
+    Unbounded,
+}
+
+const DEFAULT_QUEUE_LIMIT: usize = 1000;
+
+struct StreamQueue {
+    limit: usize,
+    policy: OverflowPolicy,
+    values: VecDeque<String>,
+    dropped: u64,
+    /// Dropped count as of the last log message.
+    dropped_logged: u64,
+}
+
+impl StreamQueue {
+    fn new(limit: usize, policy: OverflowPolicy) -> Self {
+        return StreamQueue {
+            limit: limit.max(1),
+            policy: policy,
+            values: VecDeque::new(),
+            dropped: 0,
+            dropped_logged: 0,
+        };
+    }
+
+    fn push(&mut self, value: String) {
+        match self.policy {
+            OverflowPolicy::DropOldest => {
+                if self.values.len() >= self.limit {
+                    self.values.pop_front();
+                    self.dropped += 1;
+                }
+                self.values.push_back(value);
+            },
+            OverflowPolicy::DropNewest => {
+                if self.values.len() >= self.limit {
+                    self.dropped += 1;
+                } else {
+                    self.values.push_back(value);
+                }
+            },
+            OverflowPolicy::Latest => {
+                self.dropped += self.values.len() as u64;
+                self.values.clear();
+                self.values.push_back(value);
+            },
+            OverflowPolicy::Unbounded => {
+                self.values.push_back(value);
+            },
+        }
+    }
+}
+
+/// Bounded per-stream queues of values waiting to be passed to stream callbacks.
+/// Producers add values from any thread and the main thread drains them all at
+/// once, so a fast producer can't flood the event loop.
+pub struct StreamQueues {
+    event_loop: EventLoopProxy<UserEvent>,
+    queues: Mutex<HashMap<usize, StreamQueue>>,
+    /// A `FlushStreams` event has been sent but not yet handled.
+    flush_pending: AtomicBool,
+}
+
+impl StreamQueues {
+    pub fn new(event_loop: EventLoopProxy<UserEvent>) -> Self {
+        return StreamQueues {
+            event_loop: event_loop,
+            queues: Mutex::new(HashMap::new()),
+            flush_pending: AtomicBool::new(false),
+        };
+    }
+
+    /// Set the limits for stream callback `id`. Streams that aren't configured use
+    /// the defaults.
+    pub fn configure(&self, id: usize, limit: Option<usize>, policy: OverflowPolicy) {
+        self.queues.lock().unwrap().insert(id, StreamQueue::new(limit.unwrap_or(DEFAULT_QUEUE_LIMIT), policy));
+    }
+
+    /// Queue json values for stream callback `id`.
+    pub fn send(&self, id: usize, values: impl IntoIterator<Item = String>) {
+        {
+            let mut queues = self.queues.lock().unwrap();
+            let queue =
+                queues.entry(id).or_insert_with(|| StreamQueue::new(DEFAULT_QUEUE_LIMIT, OverflowPolicy::default()));
+            for value in values {
+                queue.push(value);
+            }
+        }
+        if !self.flush_pending.swap(true, Ordering::AcqRel) {
+            match self.event_loop.send_event(UserEvent::FlushStreams) {
+                Ok(_) => (),
+                Err(_) => (),
+            };
+        }
+    }
+
+    /// Take all queued values as scripts calling the callbacks, with the window to
+    /// run each in. Main thread.
+    pub fn drain(&self, log: &loga::Log) -> Vec<(usize, String)> {
+        self.flush_pending.store(false, Ordering::Release);
+        let mut out = vec![];
+        for (id, queue) in self.queues.lock().unwrap().iter_mut() {
+            if queue.dropped != queue.dropped_logged {
+                log.log_with(
+                    loga::DEBUG,
+                    "Dropped stream values, page isn't keeping up",
+                    ea!(stream = id, dropped = queue.dropped - queue.dropped_logged, total_dropped = queue.dropped),
+                );
+                queue.dropped_logged = queue.dropped;
+            }
+            if queue.values.is_empty() {
+                continue;
+            }
+            let values = queue.values.drain(..).collect::<Vec<_>>();
+            out.push((window_of(*id), stream_cb_script(*id, &values)));
+        }
+        return out;
+    }
+
+    /// Forget all queues, for when the page navigates and the callbacks are gone.
+    pub fn clear(&self) {
+        self.queues.lock().unwrap().clear();
+    }
+
+    /// Forget the queues for callbacks in a window, for when a popup closes.
+    pub fn clear_window(&self, window: usize) {
+        self.queues.lock().unwrap().retain(|id, _| window_of(*id) != window);
+    }
+}
+
+/// Report a parse error for stream `id` to the error callback if there is one,
+/// otherwise the console.
+pub fn send_stream_error(queues: &StreamQueues, id: usize, error_id: Option<usize>, error: &str) {
+    let error = serde_json::to_string(error).unwrap();
+    match error_id {
+        Some(error_id) => {
+            queues.send(error_id, [error]);
+        },
+        None => {
+            match queues.event_loop.send_event(UserEvent::Script(window_of(id), format!("console.warn({});", error))) {
+                Ok(_) => (),
+                Err(_) => (),
+            };
+        },
+    }
+}
+
+/// Read items from `reader` until the end of the output, passing them to stream
+/// callback `id`. If `batch` is set, values received within that time of the
+/// first pending value are delivered together.
+pub async fn deliver<R: AsyncRead + Unpin>(
+    mut reader: StreamReader<R>,
+    queues: &StreamQueues,
+    id: usize,
+    error_id: Option<usize>,
+    batch: Option<Duration>,
+) -> Result<(), loga::Error> {
+    let mut pending = vec![];
+    let mut deadline = Instant::now();
+    loop {
+        let item = if pending.is_empty() {
+            reader.next().await
+        } else {
+            select!{
+                item = reader.next() => item,
+                _ = sleep_until(deadline) => {
+                    queues.send(id, pending.drain(..));
+                    continue;
+                }
+            }
+        };
+        match item.context("Error reading output")? {
+            Some(StreamItem::Value(v)) => {
+                match batch {
+                    Some(batch) => {
+                        if pending.is_empty() {
+                            deadline = Instant::now() + batch;
+                        }
+                        pending.push(v);
+                    },
+                    None => {
+                        queues.send(id, [v]);
+                    },
+                }
+            },
+            Some(StreamItem::Error(e)) => {
+                // Keep errors in order with values
+                if !pending.is_empty() {
+                    queues.send(id, pending.drain(..));
+                }
+                send_stream_error(queues, id, error_id, &e);
+            },
+            None => {
+                break;
+            },
+        }
+    }
+    if !pending.is_empty() {
+        queues.send(id, pending);
+    }
+    return Ok(());
+}
@@ -0,0 +1,154 @@
+use {
+    crate::{
+        popup::MAIN_WINDOW,
+        UserEvent,
+    },
+    futures_util::StreamExt,
+    loga::ResultContext,
+    serde_json::json,
+    std::time::Duration,
+    tao::event_loop::EventLoopProxy,
+    tokio::{
+        select,
+        time::interval,
+    },
+    zbus::zvariant::OwnedObjectPath,
+};
+
+const LOGIND: &str = "org.freedesktop.login1";
+
+/// Wall clock changes (relative to time passed) bigger than this are considered
+/// jumps.
+const JUMP_THRESHOLD: Duration = Duration::from_secs(2);
+
+fn clock_ns(clock: libc::clockid_t) -> i128 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(clock, &mut ts);
+    }
+    return ts.tv_sec as i128 * 1_000_000_000 + ts.tv_nsec as i128;
+}
+
+/// Detects wall clock jumps (suspend/resume, manual or ntp changes) by comparing
+/// how far `CLOCK_REALTIME` and `CLOCK_BOOTTIME` (which counts time suspended)
+/// have moved since the last check.
+struct ClockJumpDetector {
+    boot: i128,
+    real: i128,
+}
+
+impl ClockJumpDetector {
+    fn new() -> Self {
+        return ClockJumpDetector {
+            boot: clock_ns(libc::CLOCK_BOOTTIME),
+            real: clock_ns(libc::CLOCK_REALTIME),
+        };
+    }
+
+    /// Returns how far the wall clock jumped (in ns, negative if backwards) since
+    /// the last call, if it jumped.
+    fn check(&mut self) -> Option<i128> {
+        let boot = clock_ns(libc::CLOCK_BOOTTIME);
+        let real = clock_ns(libc::CLOCK_REALTIME);
+        let jump = (real - self.real) - (boot - self.boot);
+        self.boot = boot;
+        self.real = real;
+        if jump.unsigned_abs() > JUMP_THRESHOLD.as_nanos() {
+            return Some(jump);
+        } else {
+            return None;
+        }
+    }
+}
+
+fn send_event(event_loop: &EventLoopProxy<UserEvent>, event: serde_json::Value) {
+    match event_loop.send_event(UserEvent::Script(MAIN_WINDOW, format!("window._wongus.system_event({});", event))) {
+        Ok(_) => (),
+        Err(_) => (),
+    };
+}
+
+/// Watch logind for suspend/resume and session lock/unlock, and the wall clock for
+/// jumps, sending them to the page. Lock state is also sent to the main thread
+/// (for hiding the window).
+///
+/// Lock state follows the session's `LockedHint`, which lockers set while they're
+/// active. The `Lock`/`Unlock` signals are requests to lockers, and `Unlock` isn't
+/// sent when a locker unlocks by itself.
+pub async fn watch(event_loop: EventLoopProxy<UserEvent>) -> Result<(), loga::Error> {
+    let dbus = zbus::Connection::system().await.context("Error connecting to system D-Bus")?;
+    let manager =
+        zbus::Proxy::new(&dbus, LOGIND, "/org/freedesktop/login1", "org.freedesktop.login1.Manager")
+            .await
+            .context("Error creating logind manager proxy")?;
+    let session_path =
+        manager
+            .call::<_, _, OwnedObjectPath>("GetSession", &("auto",))
+            .await
+            .context("Error looking up current logind session")?;
+    let session =
+        zbus::Proxy::new(&dbus, LOGIND, session_path, "org.freedesktop.login1.Session")
+            .await
+            .context("Error creating logind session proxy")?;
+    let mut sleep_signals =
+        manager.receive_signal("PrepareForSleep").await.context("Error subscribing to PrepareForSleep")?;
+    let mut locked_changes = session.receive_property_changed::<bool>("LockedHint").await;
+    let mut locked = session.get_property::<bool>("LockedHint").await.context("Error getting session LockedHint")?;
+    if locked {
+        match event_loop.send_event(UserEvent::SessionLocked(true)) {
+            Ok(_) => (),
+            Err(_) => (),
+        };
+    }
+    let mut jumps = ClockJumpDetector::new();
+    let mut jump_check = interval(Duration::from_secs(1));
+    loop {
+        select!{
+            m = sleep_signals.next() => {
+                let Some(m) = m else {
+                    return Err(loga::err("PrepareForSleep signal stream ended"));
+                };
+                let start = m.body().deserialize::<bool>().context("Error parsing PrepareForSleep signal")?;
+                send_event(&event_loop, json!({
+                    "type": if start {
+                        "sleep"
+                    } else {
+                        "resume"
+                    }
+                }));
+            },
+            m = locked_changes.next() => {
+                let Some(m) = m else {
+                    return Err(loga::err("LockedHint change stream ended"));
+                };
+                let new_locked = m.get().await.context("Error getting changed session LockedHint")?;
+                if new_locked == locked {
+                    continue;
+                }
+                locked = new_locked;
+                send_event(&event_loop, json!({
+                    "type": if locked {
+                        "lock"
+                    } else {
+                        "unlock"
+                    }
+                }));
+                match event_loop.send_event(UserEvent::SessionLocked(locked)) {
+                    Ok(_) => (),
+                    Err(_) => (),
+                };
+            },
+            _ = jump_check.tick() => {
+                if let Some(jump) = jumps.check() {
+                    send_event(&event_loop, json!({
+                        "type": "clock_jump",
+                        "jump_ms": (jump / 1_000_000) as i64
+                    }));
+                }
+            },
+        }
+    }
+}
@@ -1,10 +1,21 @@
 use {
+    chrono::{
+        DateTime,
+        Offset,
+        TimeZone,
+    },
     schemars::JsonSchema,
     serde::Deserialize,
-    std::path::PathBuf,
+    std::{
+        collections::{
+            BTreeMap,
+            HashMap,
+        },
+        path::PathBuf,
+    },
 };
 
-#[derive(Deserialize, JsonSchema, Clone, Copy)]
+#[derive(Deserialize, JsonSchema, Clone, Debug)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
 pub enum P2 {
     /// Not pixels, but a delusion that will become a pixel once a scaling factor is
@@ -14,6 +25,173 @@ pub enum P2 {
     Percent(f64),
     /// Centimeters
     Cm(f64),
+    /// Millimeters
+    Mm(f64),
+    Inch(f64),
+    /// Points (1/72 inch)
+    Pt(f64),
+    /// Device pixels (logical pixels times the monitor scale factor).
+    Physical(f64),
+    /// The sum of the sizes, like `[{"percent": 100}, {"logical": -40}]` for the
+    /// monitor size minus 40 logical pixels.
+    Calc(Vec<P2>),
+    /// The smallest of the sizes.
+    Min(Vec<P2>),
+    /// The largest of the sizes.
+    Max(Vec<P2>),
+    /// Fit the page content. The page's `body` is measured, so it should be sized
+    /// by its content (ex: `width: max-content`). Only for `width` and `height`,
+    /// elsewhere this is the same as `min`.
+    Auto(AutoSize),
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Debug, Default)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct AutoSize {
+    /// Never smaller than this, and the size before the page reports its content
+    /// size.
+    #[serde(default)]
+    pub min: Option<Box<P2>>,
+    /// Never larger than this.
+    #[serde(default)]
+    pub max: Option<Box<P2>>,
+}
+
+/// Dots per inch assumed when the monitor's physical size is unknown.
+const FALLBACK_DPI: f64 = 96.;
+
+/// A monitor dimension, for converting sizes along it.
+#[derive(Clone, Copy, Debug)]
+pub struct MonitorAxis {
+    /// Size in logical pixels.
+    pub logical: i32,
+    /// Physical size, 0 if unknown.
+    pub mm: i32,
+    /// Device pixels per logical pixel.
+    pub scale: f64,
+}
+
+impl P2 {
+    /// The size in (fractional) logical pixels along `axis`.
+    pub fn to_logical(&self, axis: MonitorAxis) -> f64 {
+        let from_mm = |mm: f64| {
+            if axis.mm > 0 {
+                return mm * axis.logical as f64 / axis.mm as f64;
+            } else {
+                return mm / 25.4 * FALLBACK_DPI;
+            }
+        };
+        match self {
+            P2::Logical(p) => return *p as f64,
+            P2::Percent(p) => return axis.logical as f64 * p / 100.,
+            P2::Cm(p) => return from_mm(p * 10.),
+            P2::Mm(p) => return from_mm(*p),
+            P2::Inch(p) => return from_mm(p * 25.4),
+            P2::Pt(p) => return from_mm(p * 25.4 / 72.),
+            P2::Physical(p) => return p / axis.scale,
+            P2::Calc(parts) => return parts.iter().map(|p| p.to_logical(axis)).sum(),
+            P2::Min(parts) => return parts.iter().map(|p| p.to_logical(axis)).reduce(f64::min).unwrap_or(0.),
+            P2::Max(parts) => return parts.iter().map(|p| p.to_logical(axis)).reduce(f64::max).unwrap_or(0.),
+            P2::Auto(auto) => return auto.min.as_ref().map(|p| p.to_logical(axis)).unwrap_or(0.),
+        }
+    }
+
+    pub fn is_auto(&self) -> bool {
+        return matches!(self, P2::Auto(_));
+    }
+
+    /// The size in whole logical pixels along `axis`, rounded up and at least 1.
+    pub fn to_logical_px(&self, axis: MonitorAxis) -> i32 {
+        return (self.to_logical(axis).ceil() as i32).max(1);
+    }
+
+    /// Like `to_logical_px`, but `auto` sizes fit `content` (the page content size in
+    /// logical pixels, once known) within their bounds.
+    pub fn fit_logical_px(&self, axis: MonitorAxis, content: Option<f64>) -> i32 {
+        let (P2::Auto(auto), Some(mut size)) = (self, content) else {
+            return self.to_logical_px(axis);
+        };
+        if let Some(max) = &auto.max {
+            size = size.min(max.to_logical(axis));
+        }
+        if let Some(min) = &auto.min {
+            size = size.max(min.to_logical(axis));
+        }
+        return (size.ceil() as i32).max(1);
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceRestart {
+    /// Restart the service if it exits with a non-zero status or is killed.
+    #[default]
+    OnFailure,
+    /// Restart the service whenever it exits.
+    Always,
+}
+
+/// A way of identifying a monitor.
+#[derive(Deserialize, JsonSchema, Clone, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum MonitorSelector {
+    /// Connector name, like `DP-1` or `HDMI-A-2` (exact).
+    Connector(String),
+    /// Any monitor whose description contains this string (case insensitive). The
+    /// description usually includes the make, model, and serial number, so this can
+    /// tell apart identical monitors.
+    Description(String),
+    /// Any monitor whose model contains this string (case insensitive).
+    Model(String),
+    /// Position in the monitor list. Indexes can change when monitors are added or
+    /// removed.
+    Index(usize),
+    /// The primary monitor.
+    Primary,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum OnMissingMonitor {
+    /// Use the primary monitor (or any monitor if there's no primary).
+    #[default]
+    UsePrimary,
+    /// Hide the window until one of the monitors is attached.
+    Hide,
+    /// Exit with an error.
+    Exit,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyboardMode {
+    /// Never receive keyboard focus.
+    None,
+    /// Receive keyboard focus when clicked, like a normal window.
+    OnDemand,
+    /// Take keyboard focus and keep it, preventing other windows from getting it.
+    Exclusive,
+}
+
+#[derive(Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct ServiceConfig {
+    pub command: Vec<String>,
+    /// By default uses the working directory of `wongus`.
+    #[serde(default)]
+    pub working_dir: Option<PathBuf>,
+    /// Add to environment inherited from `wongus` process.
+    #[serde(default)]
+    pub environment: HashMap<String, String>,
+    #[serde(default)]
+    pub restart: ServiceRestart,
+    /// Delay before the first restart, doubling on each consecutive restart up to
+    /// `restart_max_delay_secs`. Defaults to 1s.
+    #[serde(default)]
+    pub restart_delay_secs: Option<f64>,
+    /// Defaults to 60s.
+    #[serde(default)]
+    pub restart_max_delay_secs: Option<f64>,
 }
 
 #[derive(Deserialize, JsonSchema, Clone)]
@@ -21,13 +199,22 @@ pub enum P2 {
 pub struct Config {
     #[serde(rename = "$schema", skip_serializing)]
     pub _schema: Option<String>,
-    /// Monitor to put the wongus on.
+    /// Monitor to put the wongus on. Same as an `index` selector in `monitors`,
+    /// ignored if `monitors` is set.
     #[serde(default)]
     pub monitor_index: Option<usize>,
     /// Monitor to put the wongus on. Any monitor with the model containing this string
-    /// will match (case insensitive).
+    /// will match (case insensitive). Same as a `model` selector in `monitors`,
+    /// ignored if `monitors` is set.
     #[serde(default)]
     pub monitor_model: Option<String>,
+    /// Monitors to put the wongus on, in order of preference. The window moves to the
+    /// most preferred attached monitor whenever monitors are attached or detached.
+    #[serde(default)]
+    pub monitors: Vec<MonitorSelector>,
+    /// What to do when none of the monitors in `monitors` are attached.
+    #[serde(default)]
+    pub on_missing: OnMissingMonitor,
     /// Attach the top of the window to the top of the screen, stretching if the
     /// opposite is also attached.
     #[serde(default)]
@@ -51,13 +238,210 @@ pub struct Config {
     #[serde(default)]
     pub height: Option<P2>,
     /// Enable keyboard interaction (enables keyboard focus, required for keyboard
-    /// interaction).
+    /// interaction). Same as `keyboard_mode` `exclusive`, ignored if `keyboard_mode`
+    /// is set.
     #[serde(default)]
     pub enable_keyboard: bool,
+    /// How the window gets keyboard focus. Defaults to `none` (or `exclusive` if
+    /// `enable_keyboard` is set).
+    #[serde(default)]
+    pub keyboard_mode: Option<KeyboardMode>,
     /// Window title.
     #[serde(default)]
     pub title: Option<String>,
     /// Http over unix domain socket for `curl`-based IPC.
     #[serde(default)]
     pub listen: Option<PathBuf>,
+    /// Absolute paths of files or directories outside the config directory that the
+    /// page can load, via `wongus-file://x/absolute/path` URLs.
+    #[serde(default)]
+    pub serve_paths: Vec<PathBuf>,
+    /// Commands to start with `wongus` and keep running, by name. Output is logged
+    /// and can be subscribed to with `wongus.services.subscribe`.
+    #[serde(default)]
+    pub services: BTreeMap<String, ServiceConfig>,
+    /// Hide the window while the login session is locked (per logind's `LockedHint`),
+    /// for lock screens that don't cover layer shell surfaces.
+    #[serde(default)]
+    pub hide_when_locked: bool,
+    /// Start with clicks passing through the window to whatever's beneath it. Use
+    /// `wongus.window.set_input_region` to make parts of it interactive.
+    #[serde(default)]
+    pub input_passthrough: bool,
+}
+
+/// When a scheduled callback runs.
+pub enum ScheduleWhen {
+    Cron(Box<cron::Schedule>),
+    /// Every this many milliseconds, at multiples of the interval since the unix
+    /// epoch in local time (so hourly runs are on the hour in zones with a partial
+    /// hour offset, and daily runs are at midnight).
+    Interval(u64),
+}
+
+impl ScheduleWhen {
+    /// The first time strictly after `now`, in `now`'s time zone.
+    pub fn next<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        match self {
+            ScheduleWhen::Cron(schedule) => {
+                return schedule.after(now).next();
+            },
+            ScheduleWhen::Interval(interval_ms) => {
+                let interval_ms = i64::try_from(*interval_ms).ok()?;
+                let offset_ms = now.offset().fix().local_minus_utc() as i64 * 1000;
+                let local_ms = now.timestamp_millis() + offset_ms;
+                let next_local_ms = local_ms.div_euclid(interval_ms).checked_add(1)?.checked_mul(interval_ms)?;
+                return DateTime::from_timestamp_millis(
+                    next_local_ms - offset_ms,
+                ).map(|t| t.with_timezone(&now.timezone()));
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            AutoSize,
+            MonitorAxis,
+            ScheduleWhen,
+            P2,
+        },
+        chrono::{
+            DateTime,
+            FixedOffset,
+        },
+        std::str::FromStr,
+    };
+
+    /// 1920 logical px across 600mm at scale 2
+    const AXIS: MonitorAxis = MonitorAxis {
+        logical: 1920,
+        mm: 600,
+        scale: 2.,
+    };
+
+    #[test]
+    fn logical() {
+        assert_eq!(P2::Logical(45).to_logical(AXIS), 45.);
+    }
+
+    #[test]
+    fn percent() {
+        assert_eq!(P2::Percent(50.).to_logical(AXIS), 960.);
+    }
+
+    #[test]
+    fn physical_units() {
+        assert_eq!(P2::Cm(1.).to_logical(AXIS), 32.);
+        assert_eq!(P2::Mm(10.).to_logical(AXIS), 32.);
+        assert_eq!(P2::Inch(1.).to_logical(AXIS), 81.28);
+        assert!((P2::Pt(72.).to_logical(AXIS) - 81.28).abs() < 1e-9);
+    }
+
+    #[test]
+    fn physical_units_use_own_axis() {
+        // Non-square: height axis has a different px/mm than width
+        let height = MonitorAxis {
+            logical: 1080,
+            mm: 270,
+            scale: 1.,
+        };
+        assert_eq!(P2::Cm(1.).to_logical(height), 40.);
+    }
+
+    #[test]
+    fn unknown_physical_size() {
+        let axis = MonitorAxis {
+            logical: 1920,
+            mm: 0,
+            scale: 1.,
+        };
+        assert_eq!(P2::Inch(1.).to_logical(axis), 96.);
+    }
+
+    #[test]
+    fn physical_pixels() {
+        assert_eq!(P2::Physical(90.).to_logical(AXIS), 45.);
+    }
+
+    #[test]
+    fn calc() {
+        assert_eq!(P2::Calc(vec![P2::Percent(100.), P2::Logical(-40)]).to_logical(AXIS), 1880.);
+    }
+
+    #[test]
+    fn min_max() {
+        assert_eq!(P2::Min(vec![P2::Percent(50.), P2::Logical(300)]).to_logical(AXIS), 300.);
+        assert_eq!(P2::Max(vec![P2::Percent(50.), P2::Logical(300)]).to_logical(AXIS), 960.);
+        assert_eq!(
+            P2::Max(vec![P2::Logical(100), P2::Min(vec![P2::Percent(10.), P2::Logical(150)])]).to_logical(AXIS),
+            150.
+        );
+    }
+
+    #[test]
+    fn px_rounds_up() {
+        assert_eq!(P2::Physical(45.).to_logical_px(AXIS), 23);
+        assert_eq!(P2::Logical(0).to_logical_px(AXIS), 1);
+    }
+
+    #[test]
+    fn auto() {
+        let auto = P2::Auto(AutoSize {
+            min: Some(Box::new(P2::Logical(100))),
+            max: Some(Box::new(P2::Percent(25.))),
+        });
+        assert_eq!(auto.fit_logical_px(AXIS, None), 100);
+        assert_eq!(auto.fit_logical_px(AXIS, Some(50.)), 100);
+        assert_eq!(auto.fit_logical_px(AXIS, Some(200.4)), 201);
+        assert_eq!(auto.fit_logical_px(AXIS, Some(1000.)), 480);
+        assert_eq!(P2::Auto(AutoSize::default()).fit_logical_px(AXIS, Some(3000.)), 3000);
+        assert_eq!(P2::Logical(45).fit_logical_px(AXIS, Some(3000.)), 45);
+    }
+
+    #[test]
+    fn parse() {
+        let p = serde_json::from_str::<P2>(r#"{"calc": [{"percent": 100}, {"mm": -5}]}"#).unwrap();
+        assert_eq!(p.to_logical(AXIS), 1920. - 16.);
+    }
+
+    fn at(s: &str) -> DateTime<FixedOffset> {
+        return DateTime::parse_from_rfc3339(s).unwrap();
+    }
+
+    #[test]
+    fn interval_next() {
+        let hourly = ScheduleWhen::Interval(60 * 60 * 1000);
+        assert_eq!(hourly.next(&at("2024-03-01T10:15:00Z")).unwrap(), at("2024-03-01T11:00:00Z"));
+        assert_eq!(hourly.next(&at("2024-03-01T10:00:00Z")).unwrap(), at("2024-03-01T11:00:00Z"));
+        let minutely = ScheduleWhen::Interval(60 * 1000);
+        assert_eq!(minutely.next(&at("2024-03-01T10:15:59.999Z")).unwrap(), at("2024-03-01T10:16:00Z"));
+    }
+
+    #[test]
+    fn interval_next_local_offset() {
+        // On the hour locally, not on the UTC hour
+        let hourly = ScheduleWhen::Interval(60 * 60 * 1000);
+        assert_eq!(hourly.next(&at("2024-03-01T10:15:00+05:30")).unwrap(), at("2024-03-01T11:00:00+05:30"));
+        let daily = ScheduleWhen::Interval(24 * 60 * 60 * 1000);
+        assert_eq!(daily.next(&at("2024-03-01T23:59:00-08:00")).unwrap(), at("2024-03-02T00:00:00-08:00"));
+    }
+
+    #[test]
+    fn interval_next_overflow() {
+        assert!(ScheduleWhen::Interval(u64::MAX).next(&at("2024-03-01T10:15:00Z")).is_none());
+    }
+
+    #[test]
+    fn cron_next() {
+        let when = ScheduleWhen::Cron(Box::new(cron::Schedule::from_str("0 30 9 * * Mon-Fri").unwrap()));
+
+        // Friday evening -> Monday morning, local time
+        assert_eq!(when.next(&at("2024-03-01T18:00:00+05:30")).unwrap(), at("2024-03-04T09:30:00+05:30"));
+
+        // Strictly after
+        assert_eq!(when.next(&at("2024-03-04T09:30:00+05:30")).unwrap(), at("2024-03-05T09:30:00+05:30"));
+    }
 }
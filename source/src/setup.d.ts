@@ -1,9 +1,21 @@
 declare interface Window {
   _wongus: {
-    stream_cbs: Map<number, (line: string) => void>;
+    stream_cbs: Map<number, (value: any) => void>;
     responses: Map<number, (body: any) => void>;
     external_ipc: (id: number, args: any) => void;
+    system_event_cbs: ((event: WongusSystemEvent) => void)[];
+    system_event: (event: WongusSystemEvent) => void;
+    monitor_event_cbs: ((event: WongusMonitorEvent) => void)[];
+    monitor_event: (event: WongusMonitorEvent) => void;
+    popup_channels: Map<
+      number,
+      { message_cbs: ((message: any) => void)[]; close_cbs: (() => void)[] }
+    >;
+    popup_message: (from: number, message: any) => void;
+    popup_closed: (id: number) => void;
   };
+  _wongus_window_id?: number;
+  _wongus_auto_size?: boolean;
   ipc: {
     postMessage: (message: string) => void;
   };
@@ -5,9 +5,45 @@ window._wongus = {
   stream_cbs: new Map(),
   responses: new Map(),
   external_ipc: null,
+  system_event_cbs: [],
+  system_event: (event) => {
+    for (const cb of window._wongus.system_event_cbs) {
+      cb(event);
+    }
+  },
+  monitor_event_cbs: [],
+  monitor_event: (event) => {
+    for (const cb of window._wongus.monitor_event_cbs) {
+      cb(event);
+    }
+  },
+  popup_channels: new Map(),
+  popup_message: (from, message) => {
+    const channel = window._wongus.popup_channels.get(from);
+    if (channel == null) {
+      return;
+    }
+    for (const cb of channel.message_cbs) {
+      cb(message);
+    }
+  },
+  popup_closed: (id) => {
+    const channel = window._wongus.popup_channels.get(id);
+    if (channel == null) {
+      return;
+    }
+    window._wongus.popup_channels.delete(id);
+    for (const cb of channel.close_cbs) {
+      cb();
+    }
+  },
 };
-var next_ipc_id = 0;
-var next_stream_command_id = 0;
+
+// 0 for the main page, set before this script runs in popups. Ids are allocated
+// from a range per window so wongus can route responses and callbacks.
+const wongus_window_id = window._wongus_window_id || 0;
+var next_ipc_id = wongus_window_id * 2 ** 32;
+var next_stream_command_id = wongus_window_id * 2 ** 32;
 
 /**
  *
@@ -47,6 +83,25 @@ const wongus_ipc = (args) => {
   return out;
 };
 
+/**
+ * Message handlers for a popup (from the main page) or the main page (from a
+ * popup).
+ * @param {number} id
+ */
+const popup_channel = (id) => {
+  const channel = { message_cbs: [], close_cbs: [] };
+  window._wongus.popup_channels.set(id, channel);
+  return {
+    channel: channel,
+    post: async (message) => {
+      return await wongus_ipc({ popup_post: { to: id, message: message } });
+    },
+    on_message: (cb) => {
+      channel.message_cbs.push(cb);
+    },
+  };
+};
+
 /**
  *
  * @param {number} id
@@ -76,12 +131,40 @@ window.wongus = {
   list_dir: async (path) => {
     return await wongus_ipc({ list_dir: path });
   },
+  list_dir_stat: async (args) => {
+    return await wongus_ipc({ list_dir_stat: args });
+  },
+  stat: async (path) => {
+    return await wongus_ipc({ stat: path });
+  },
   file_exists: async (path) => {
     return await wongus_ipc({ file_exists: path });
   },
   read: async (path) => {
     return await wongus_ipc({ read: path });
   },
+  read_bytes: async (args) => {
+    const b64 = await wongus_ipc({ read_bytes: args });
+    return Uint8Array.from(atob(b64), (c) => c.charCodeAt(0)).buffer;
+  },
+  read_tail: async (path, lines) => {
+    return await wongus_ipc({ read_tail: { path: path, lines: lines } });
+  },
+  write: async (args) => {
+    return await wongus_ipc({ write: args });
+  },
+  append: async (args) => {
+    return await wongus_ipc({ append: args });
+  },
+  create_dir: async (path) => {
+    return await wongus_ipc({ create_dir: path });
+  },
+  remove: async (args) => {
+    return await wongus_ipc({ remove: args });
+  },
+  rename: async (from, to) => {
+    return await wongus_ipc({ rename: { from: from, to: to } });
+  },
   run_command: async (args) => {
     return await wongus_ipc({ run_command: args });
   },
@@ -90,15 +173,262 @@ window.wongus = {
   },
   stream_command: async (args) => {
     const cb_id = next_stream_command_id++;
-    window._wongus.stream_cbs.set(cb_id, args.cb);
-    return await wongus_ipc({
+    if (args.mode == "raw_chunks" || args.pty != null) {
+      window._wongus.stream_cbs.set(cb_id, (b64) => {
+        args.cb(Uint8Array.from(atob(b64), (c) => c.charCodeAt(0)).buffer);
+      });
+    } else {
+      window._wongus.stream_cbs.set(cb_id, args.cb);
+    }
+    let error_id = null;
+    if (args.on_error != null) {
+      error_id = next_stream_command_id++;
+      window._wongus.stream_cbs.set(error_id, args.on_error);
+    }
+    const resp = await wongus_ipc({
       stream_command: {
         id: cb_id,
         command: args.command,
         working_dir: args.working_dir,
         environment: args.environment,
+        shared: args.shared,
+        replay: args.replay,
+        mode: args.mode,
+        error_id: error_id,
+        batch_ms: args.batch_ms,
+        queue_limit: args.queue_limit,
+        overflow: args.overflow,
+        pty: args.pty,
+      },
+    });
+    if (args.pty != null) {
+      return {
+        write: async (data) => {
+          if (typeof data == "string") {
+            return await wongus_ipc({ pty_write: { id: cb_id, text: data } });
+          } else {
+            let bin = "";
+            for (const b of new Uint8Array(data)) {
+              bin += String.fromCharCode(b);
+            }
+            return await wongus_ipc({ pty_write: { id: cb_id, base64: btoa(bin) } });
+          }
+        },
+        resize: async (rows, cols) => {
+          return await wongus_ipc({ pty_resize: { id: cb_id, rows: rows, cols: cols } });
+        },
+      };
+    }
+    return resp;
+  },
+  backlight: {
+    list: async () => {
+      return await wongus_ipc({ backlight_list: null });
+    },
+    get: async (name) => {
+      return await wongus_ipc({ backlight_get: name });
+    },
+    set: async (args) => {
+      return await wongus_ipc({ backlight_set: args });
+    },
+    watch: async (name, cb) => {
+      const cb_id = next_stream_command_id++;
+      window._wongus.stream_cbs.set(cb_id, cb);
+      return await wongus_ipc({
+        backlight_watch: {
+          id: cb_id,
+          name: name,
+        },
+      });
+    },
+  },
+  network: {
+    get: async () => {
+      return await wongus_ipc({ network_get: null });
+    },
+    watch: async (args) => {
+      const cb_id = next_stream_command_id++;
+      window._wongus.stream_cbs.set(cb_id, args.cb);
+      return await wongus_ipc({
+        network_watch: {
+          id: cb_id,
+          refresh_secs: args.refresh_secs,
+        },
+      });
+    },
+  },
+  clipboard: {
+    read_text: async () => {
+      return await wongus_ipc({ clipboard: { read_text: null } });
+    },
+    write_text: async (text) => {
+      return await wongus_ipc({ clipboard: { write_text: text } });
+    },
+    read: async (mime) => {
+      return await wongus_ipc({ clipboard: { read: mime } });
+    },
+    write: async (mime, data) => {
+      return await wongus_ipc({ clipboard: { write: { mime: mime, data: data } } });
+    },
+    on_change: async (cb) => {
+      const cb_id = next_stream_command_id++;
+      window._wongus.stream_cbs.set(cb_id, cb);
+      return await wongus_ipc({ clipboard: { watch: cb_id } });
+    },
+  },
+  storage: {
+    get: async (key) => {
+      return await wongus_ipc({ storage_get: key });
+    },
+    set: async (key, value) => {
+      return await wongus_ipc({ storage_set: { key: key, value: value } });
+    },
+    delete: async (key) => {
+      return await wongus_ipc({ storage_delete: key });
+    },
+    list: async () => {
+      return await wongus_ipc({ storage_list: null });
+    },
+  },
+  schedule: async (args, cb) => {
+    const cb_id = next_stream_command_id++;
+    window._wongus.stream_cbs.set(cb_id, cb);
+    return await wongus_ipc({
+      schedule: {
+        id: cb_id,
+        cron: args.cron,
+        interval_ms: args.interval_ms,
+        at_wallclock_minute: args.at_wallclock_minute || false,
+        command: args.command,
       },
     });
   },
+  services: {
+    subscribe: async (name, cb) => {
+      const cb_id = next_stream_command_id++;
+      window._wongus.stream_cbs.set(cb_id, cb);
+      return await wongus_ipc({
+        services_subscribe: {
+          id: cb_id,
+          name: name,
+        },
+      });
+    },
+  },
+  monitors: async () => {
+    return await wongus_ipc({ monitors: null });
+  },
+  current_monitor: async () => {
+    return await wongus_ipc({ current_monitor: null });
+  },
+  on_monitor_change: (cb) => {
+    window._wongus.monitor_event_cbs.push(cb);
+  },
+  window: {
+    hide: async () => {
+      return await wongus_ipc({ window_visibility: "hide" });
+    },
+    show: async () => {
+      return await wongus_ipc({ window_visibility: "show" });
+    },
+    toggle: async () => {
+      return await wongus_ipc({ window_visibility: "toggle" });
+    },
+    grab_keyboard: async () => {
+      return await wongus_ipc({ window_grab_keyboard: null });
+    },
+    release_keyboard: async () => {
+      return await wongus_ipc({ window_release_keyboard: null });
+    },
+    set_input_region: async (rects) => {
+      return await wongus_ipc({
+        window_set_input_region:
+          rects == null
+            ? null
+            : rects.map((r) => ({ x: r.x, y: r.y, width: r.width, height: r.height })),
+      });
+    },
+  },
+  popup: {
+    open: async (args) => {
+      const resp = await wongus_ipc({
+        popup_open: {
+          url: args.url == null ? null : new URL(args.url, window.location.href).href,
+          html: args.html,
+          anchor_rect: args.anchor_rect,
+          size: args.size,
+          layer: args.layer,
+          keyboard: args.keyboard || false,
+          close_on_leave: args.close_on_leave || false,
+        },
+      });
+      const c = popup_channel(resp.id);
+      return {
+        id: resp.id,
+        post: c.post,
+        on_message: c.on_message,
+        on_close: (cb) => {
+          c.channel.close_cbs.push(cb);
+        },
+        close: async () => {
+          return await wongus_ipc({ popup_close: resp.id });
+        },
+      };
+    },
+    parent: (() => {
+      if (wongus_window_id == 0) {
+        return null;
+      }
+      const c = popup_channel(0);
+      return {
+        post: c.post,
+        on_message: c.on_message,
+      };
+    })(),
+    close: async () => {
+      if (wongus_window_id == 0) {
+        throw new Error("Not in a popup");
+      }
+      return await wongus_ipc({ popup_close: wongus_window_id });
+    },
+  },
+  on_system_event: (cb) => {
+    window._wongus.system_event_cbs.push(cb);
+  },
   handle_external_ipc: null,
 };
+
+// Fit the window to the page for `auto` sizes. `_wongus_auto_size` is set in a
+// later init script, so wait for the page before checking it.
+document.addEventListener("DOMContentLoaded", () => {
+  if (!window._wongus_auto_size) {
+    return;
+  }
+  var timeout = null;
+  var last_width = null;
+  var last_height = null;
+  const send = () => {
+    timeout = null;
+    const rect = document.body.getBoundingClientRect();
+    const style = window.getComputedStyle(document.body);
+    const width = Math.ceil(
+      rect.width + parseFloat(style.marginLeft) + parseFloat(style.marginRight)
+    );
+    const height = Math.ceil(
+      rect.height + parseFloat(style.marginTop) + parseFloat(style.marginBottom)
+    );
+    if (width == last_width && height == last_height) {
+      return;
+    }
+    last_width = width;
+    last_height = height;
+    wongus_ipc({ window_content_size: { width: width, height: height } });
+  };
+  // Wait for changes to settle (ex: during animations) before resizing the window
+  new ResizeObserver(() => {
+    if (timeout != null) {
+      clearTimeout(timeout);
+    }
+    timeout = setTimeout(send, 50);
+  }).observe(document.body);
+});
@@ -1,3 +1,142 @@
+declare type WongusPty = {
+  /**
+   * Send input to the command, like keypresses.
+   */
+  readonly write: (data: string | ArrayBuffer) => Promise<void>;
+  readonly resize: (rows: number, cols: number) => Promise<void>;
+};
+type WongusFileStat = {
+  /**
+   * Lossy if the path isn't valid utf-8
+   */
+  path: string;
+  /**
+   * The exact path bytes, only set if the path isn't valid utf-8
+   */
+  path_bytes: number[] | null;
+  file_type: "file" | "dir" | "symlink" | "other";
+  /**
+   * Size in bytes
+   */
+  size: number;
+  /**
+   * Modification time, milliseconds since the epoch (for `new Date(mtime)`)
+   */
+  mtime: number | null;
+  /**
+   * Unix permission bits, like `0o644`
+   */
+  mode: number;
+  /**
+   * Only set for symlinks
+   */
+  symlink_target: string | null;
+};
+declare type WongusBacklight = {
+  /**
+   * Device name, the directory in `/sys/class/backlight`
+   */
+  name: string;
+  /**
+   * Current brightness in device units
+   */
+  brightness: number;
+  max_brightness: number;
+  /**
+   * Current brightness as a percent of `max_brightness` (0-100)
+   */
+  percent: number;
+};
+declare type WongusNetworkInterface = {
+  index: number;
+  name: string;
+  state: "up" | "down" | "dormant" | "lower_layer_down" | "not_present" | "testing" | "unknown";
+  mac: string | null;
+  mtu: number | null;
+  addresses: { address: string; prefix_len: number }[];
+  rx_bytes: number;
+  tx_bytes: number;
+  rx_packets: number;
+  tx_packets: number;
+  /**
+   * Only set for wireless interfaces with an active connection, when NetworkManager is running
+   */
+  wifi: {
+    ssid: string;
+    /**
+     * 0-100
+     */
+    strength: number;
+  } | null;
+};
+declare type WongusSystemEvent =
+  | { type: "sleep" }
+  | { type: "resume" }
+  | { type: "lock" }
+  | { type: "unlock" }
+  | {
+      type: "clock_jump";
+      /**
+       * How far the wall clock moved relative to elapsed time, negative if it went backwards
+       */
+      jump_ms: number;
+    };
+declare type WongusRect = { x: number; y: number; width: number; height: number };
+declare type WongusMonitor = {
+  /**
+   * Position in the monitor list. Indexes can change when monitors are added or removed. null for removed monitors.
+   */
+  index: number | null;
+  manufacturer: string | null;
+  model: string | null;
+  /**
+   * Area of the monitor in the global (logical pixel) coordinate space.
+   */
+  geometry: WongusRect;
+  /**
+   * Area of the monitor not reserved by panels (on Wayland this is usually the same as `geometry`).
+   */
+  workarea: WongusRect;
+  /**
+   * Integer scale factor (device pixels per logical pixel).
+   */
+  scale: number;
+  /**
+   * Physical size, 0 if unknown.
+   */
+  width_mm: number;
+  height_mm: number;
+  /**
+   * Refresh rate in Hz, 0 if unknown.
+   */
+  refresh_rate: number;
+  primary: boolean;
+};
+declare type WongusMonitorEvent = {
+  type: "added" | "removed" | "scale_changed";
+  monitor: WongusMonitor;
+};
+declare type WongusPopupChannel = {
+  /**
+   * Send a json-serializable message to the other side.
+   */
+  readonly post: (message: any) => Promise<void>;
+  /**
+   * Call `cb` with each message from the other side.
+   */
+  readonly on_message: (cb: (message: any) => void) => void;
+};
+declare type WongusPopup = WongusPopupChannel & {
+  /**
+   * The popup's window id.
+   */
+  readonly id: number;
+  /**
+   * Call `cb` when the popup closes (for any reason).
+   */
+  readonly on_close: (cb: () => void) => void;
+  readonly close: () => Promise<void>;
+};
 declare type Wongus = {
   /**
    * Commandline `k=v` arguments
@@ -14,9 +153,29 @@ declare type Wongus = {
   /**
    * List files in a directory.
    * @param path Directory to list files in.
-   * @returns A list of file paths in the directory. Each path begins with the target directory's path (i.e. not just filenames, absolute only if listed directory path is absolute).
+   * @returns A list of file paths in the directory. Each path begins with the target directory's path (i.e. not just filenames, absolute only if listed directory path is absolute). Paths that aren't valid utf-8 have the invalid parts replaced with `�`, use `list_dir_stat` to get the exact bytes.
    */
   readonly list_dir: (path: string) => Promise<string[]>;
+  /**
+   * List files in a directory with their metadata, optionally recursing into subdirectories.
+   * @returns Entries sorted by path. Each path begins with the target directory's path. Symlinks are not followed.
+   */
+  readonly list_dir_stat: (args: {
+    path: string;
+    /**
+     * Levels of directories to list, defaults to 1 (only direct children of `path`).
+     */
+    depth?: number;
+    /**
+     * Only return entries whose file name (not path) matches this glob, like `*.pdf`. Subdirectories are still traversed when recursing even if they don't match.
+     */
+    glob?: string;
+  }) => Promise<WongusFileStat[]>;
+  /**
+   * Get metadata for a file or directory, without following symlinks.
+   * @returns null if the path doesn't exist
+   */
+  readonly stat: (path: string) => Promise<WongusFileStat | null>;
   /**
    * Check if a file or directory exists.
    * @param path Path to check.
@@ -49,18 +208,314 @@ declare type Wongus = {
     pid: number;
   }>;
   /**
-   * Run a command and call `cb` with each line it writes to stdout.
+   * Run a command and call `cb` with each line (or other value, per `mode`) it writes to stdout.
    */
   readonly stream_command: (args: {
     command: string[];
     working_dir?: string;
     environment?: { [key: string]: string };
-    cb: (line: string) => void;
-  }) => void;
+    /**
+     * Share one process with other shared streams with the same command, working dir and environment, including ones started before a reload. The process is killed a few seconds after the last subscriber leaves.
+     */
+    shared?: boolean;
+    /**
+     * For shared streams, immediately call `cb` with up to this many (at most 100) of the most recent lines.
+     */
+    replay?: number;
+    /**
+     * How to split the output, defaults to `lines`.
+     *
+     * - `lines`: `cb` receives each line as a string
+     * - `json_lines`: `cb` receives each non-empty line parsed as JSON
+     * - `null_delimited`: `cb` receives each null-separated string
+     * - `raw_chunks`: `cb` receives an `ArrayBuffer` of data as it arrives
+     */
+    mode?: "lines" | "json_lines" | "null_delimited" | "raw_chunks";
+    /**
+     * Called with a message when output can't be parsed (invalid JSON or UTF-8). Errors are logged to the console if not set.
+     */
+    on_error?: (error: string) => void;
+    /**
+     * Values arriving within this many milliseconds of each other are delivered to the page together (`cb` is still called once per value). Not supported with `shared`.
+     */
+    batch_ms?: number;
+    /**
+     * Max values waiting to be delivered to the page before `overflow` applies, defaults to 1000.
+     */
+    queue_limit?: number;
+    /**
+     * What to do with values when the page falls behind, defaults to `drop_oldest`.
+     *
+     * - `drop_oldest`: discard the oldest waiting values
+     * - `drop_newest`: discard new values until the page catches up
+     * - `latest`: only deliver the most recent value
+     */
+    overflow?: "drop_oldest" | "drop_newest" | "latest";
+    /**
+     * Run the command in a pseudo-terminal with this size. `cb` receives output as `ArrayBuffer` chunks (like `raw_chunks`), and the returned promise resolves to a handle for sending input and resizing. Output is never dropped, so `queue_limit` and `overflow` are ignored. Can't be used with `shared`.
+     */
+    pty?: { rows: number; cols: number };
+    cb: (value: any) => void;
+  }) => Promise<void | WongusPty>;
   /**
    * Read a file, return the contents as a string
    */
   readonly read: (path: string) => Promise<string>;
+  /**
+   * Read binary data from a file, optionally only part of the file
+   */
+  readonly read_bytes: (args: {
+    path: string;
+    /**
+     * Byte offset to start reading at, defaults to 0
+     */
+    offset?: number;
+    /**
+     * Max number of bytes to read, defaults to reading until the end of the file
+     */
+    length?: number;
+  }) => Promise<ArrayBuffer>;
+  /**
+   * Read the last `lines` lines of a text file (like `tail -n`), without reading the whole file
+   */
+  readonly read_tail: (path: string, lines: number) => Promise<string>;
+  /**
+   * Replace the contents of a file, creating it if it doesn't exist. The file is replaced atomically (written to a temporary file then renamed), so readers never see partial contents. Exactly one of `text` or `base64` must be specified.
+   */
+  readonly write: (args: { path: string; text?: string; base64?: string }) => Promise<void>;
+  /**
+   * Append to a file, creating it if it doesn't exist. Exactly one of `text` or `base64` must be specified.
+   */
+  readonly append: (args: { path: string; text?: string; base64?: string }) => Promise<void>;
+  /**
+   * Create a directory, including any missing parent directories. Succeeds if the directory already exists.
+   */
+  readonly create_dir: (path: string) => Promise<void>;
+  /**
+   * Delete a file or directory. Non-empty directories are only deleted if `recursive` is true.
+   */
+  readonly remove: (args: { path: string; recursive?: boolean }) => Promise<void>;
+  /**
+   * Move a file or directory, replacing `to` if it's a file.
+   */
+  readonly rename: (from: string, to: string) => Promise<void>;
+  /**
+   * Read and control screen backlights via `/sys/class/backlight` and logind.
+   */
+  readonly backlight: {
+    /**
+     * List all backlight devices and their current brightness.
+     */
+    readonly list: () => Promise<WongusBacklight[]>;
+    /**
+     * Get the current brightness of a backlight device.
+     */
+    readonly get: (name: string) => Promise<WongusBacklight>;
+    /**
+     * Set the brightness of a backlight device. Exactly one of `percent` or `raw` must be specified.
+     */
+    readonly set: (args: {
+      name: string;
+      /**
+       * 0-100
+       */
+      percent?: number;
+      /**
+       * 0-`max_brightness`
+       */
+      raw?: number;
+    }) => Promise<void>;
+    /**
+     * Call `cb` with the current brightness and then again whenever it changes.
+     */
+    readonly watch: (name: string, cb: (state: WongusBacklight) => void) => Promise<void>;
+  };
+  /**
+   * Network interface state, via rtnetlink (and NetworkManager for wifi info).
+   */
+  readonly network: {
+    /**
+     * Get the current state of all network interfaces.
+     */
+    readonly get: () => Promise<WongusNetworkInterface[]>;
+    /**
+     * Call `cb` with the state of all network interfaces immediately and then whenever links or addresses change.
+     */
+    readonly watch: (args: {
+      cb: (interfaces: WongusNetworkInterface[]) => void;
+      /**
+       * Also call `cb` at this interval, for updated traffic counters and signal strength. Must be positive, values under 0.25 are raised to 0.25.
+       */
+      refresh_secs?: number;
+    }) => Promise<void>;
+  };
+  /**
+   * Access the system clipboard.
+   */
+  readonly clipboard: {
+    /**
+     * Get the clipboard contents as text, or null if the clipboard is empty or can't be converted to text.
+     */
+    readonly read_text: () => Promise<string | null>;
+    readonly write_text: (text: string) => Promise<void>;
+    /**
+     * Get the clipboard contents in the requested mime type (like `image/png`) as base64, or null if not available in that type.
+     */
+    readonly read: (mime: string) => Promise<string | null>;
+    /**
+     * Replace the clipboard contents with base64-encoded `data` of the specified mime type.
+     */
+    readonly write: (mime: string, data: string) => Promise<void>;
+    /**
+     * Call `cb` with the new clipboard text whenever the clipboard changes.
+     */
+    readonly on_change: (cb: (text: string | null) => void) => void;
+  };
+  /**
+   * Persistent key-value storage, saved as json in `$XDG_STATE_HOME/wongus/`. Use this instead of `localStorage`.
+   */
+  readonly storage: {
+    /**
+     * Get the value for `key`, or null if it isn't set.
+     */
+    readonly get: (key: string) => Promise<any>;
+    /**
+     * Set the value for `key`. `value` must be json-serializable.
+     */
+    readonly set: (key: string, value: any) => Promise<void>;
+    readonly delete: (key: string) => Promise<void>;
+    /**
+     * List all set keys.
+     */
+    readonly list: () => Promise<string[]>;
+  };
+  /**
+   * Call `cb` at scheduled times, timed by wongus rather than the page so it doesn't drift or stall. Exactly one of `cron`, `interval_ms` or `at_wallclock_minute` must be set. If times are missed (ex: while suspended) `cb` is called once as soon as possible.
+   */
+  readonly schedule: (
+    args: {
+      /**
+       * Cron expression with seconds, in local time: `sec min hour day_of_month month day_of_week [year]`
+       */
+      cron?: string;
+      /**
+       * Run every this many milliseconds, aligned to multiples of the interval since the unix epoch in local time (so `60000` runs at the start of each minute and `3600000` on the hour). Must be at least 100.
+       */
+      interval_ms?: number;
+      /**
+       * Run at the start of each minute, like for a clock.
+       */
+      at_wallclock_minute?: boolean;
+      /**
+       * Run this command each time, passing its stdout (or the error if it failed) to `cb`.
+       */
+      command?: string[];
+    },
+    cb: (event: {
+      /**
+       * Milliseconds since the epoch
+       */
+      time: number;
+      output?: string;
+      error?: string;
+    }) => void
+  ) => Promise<void>;
+  /**
+   * Long-running commands defined in `services` in `config.json`.
+   */
+  readonly services: {
+    /**
+     * Call `cb` with each line the service named `name` outputs from now on.
+     */
+    readonly subscribe: (
+      name: string,
+      cb: (output: { stream: "stdout" | "stderr"; line: string }) => void
+    ) => Promise<void>;
+  };
+  /**
+   * List all monitors.
+   */
+  readonly monitors: () => Promise<WongusMonitor[]>;
+  /**
+   * The monitor the main window is on, or null if it's not shown.
+   */
+  readonly current_monitor: () => Promise<WongusMonitor | null>;
+  /**
+   * Call `cb` when a monitor is added or removed or its scale factor changes.
+   */
+  readonly on_monitor_change: (cb: (event: WongusMonitorEvent) => void) => void;
+  /**
+   * Control the main window.
+   */
+  readonly window: {
+    /**
+     * Hide the main window, releasing the space it reserves on the screen. Also closes any popups.
+     */
+    readonly hide: () => Promise<void>;
+    readonly show: () => Promise<void>;
+    readonly toggle: () => Promise<void>;
+    /**
+     * Take keyboard focus, keeping it until `release_keyboard` is called (like `keyboard_mode` `exclusive`).
+     */
+    readonly grab_keyboard: () => Promise<void>;
+    /**
+     * Return to the configured `keyboard_mode`, or `on_demand` if it's `exclusive`.
+     */
+    readonly release_keyboard: () => Promise<void>;
+    /**
+     * Only accept clicks within these areas of the window (like `element.getBoundingClientRect()`), letting clicks elsewhere pass through to the windows beneath. An empty list makes the whole window click-through, and `null` makes the whole window accept clicks again.
+     */
+    readonly set_input_region: (
+      rects: { x: number; y: number; width: number; height: number }[] | null
+    ) => Promise<void>;
+  };
+  /**
+   * Additional windows for menus, tooltips, calendars, etc. that don't fit in the main window.
+   */
+  readonly popup: {
+    /**
+     * Open a popup beside an element of the main page, in its own layer shell surface and webview with the full `wongus` api. Only the main page can open popups. Popups close when the main window or another popup is clicked, when they lose keyboard focus (if `keyboard` is set and they've been focused), when the pointer leaves them (if `close_on_leave` is set), when closed with `close()` by the main page or the popup itself, or when the main page reloads. Clicks in other programs only close popups that have had keyboard focus.
+     */
+    readonly open: (args: {
+      /**
+       * Page to show in the popup, relative to the main page's url. Must be in the content root, a `wongus-file://` url, or on the `--server`. Mutually exclusive with `html`.
+       */
+      url?: string;
+      /**
+       * Html to show in the popup. Mutually exclusive with `url`.
+       */
+      html?: string;
+      /**
+       * Area of the main page (like `element.getBoundingClientRect()`) to place the popup beside. The popup is placed on the side away from the screen edge the main window is attached to, kept within the monitor.
+       */
+      anchor_rect: { x: number; y: number; width: number; height: number };
+      size: { width: number; height: number };
+      /**
+       * Defaults to `overlay`.
+       */
+      layer?: "top" | "overlay";
+      /**
+       * Allow the popup to receive keyboard focus, and close it when it loses focus (ex: when another program is clicked). It only loses focus after it's had it (ex: been clicked). Defaults to false.
+       */
+      keyboard?: boolean;
+      /**
+       * Close the popup when the pointer leaves it, ex: for tooltips or hover menus. Defaults to false.
+       */
+      close_on_leave?: boolean;
+    }) => Promise<WongusPopup>;
+    /**
+     * In a popup, the channel to the main page. `null` in the main page.
+     */
+    readonly parent: WongusPopupChannel | null;
+    /**
+     * In a popup, close it.
+     */
+    readonly close: () => Promise<void>;
+  };
+  /**
+   * Call `cb` when the system is about to sleep or has resumed, the login session is locked or unlocked, or the wall clock jumps (ex: after resume or an ntp adjustment). Sleep and lock events come from logind and won't be sent if it isn't available.
+   */
+  readonly on_system_event: (cb: (event: WongusSystemEvent) => void) => void;
   /**
    * Overwrite this with a callback that's called when an external process uses wongus's IPC.
    */